@@ -0,0 +1,412 @@
+use std::collections::{BTreeMap, BTreeSet};
+use crate::chip8::MAX_ROM_SIZE;
+use crate::symbols::SymbolTable;
+
+const ENTRY: u16 = 0x200;
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum EdgeKind {
+    // Straight on to the next instruction, including returning from a call
+    Fallthrough,
+    Jump,
+    // The taken side of a skip instruction
+    Skip,
+    Call,
+}
+
+pub struct BasicBlock {
+    pub start: u16,
+    // Address of the last instruction in the block
+    pub end: u16,
+    pub successors: Vec<(u16, EdgeKind)>,
+    // Ends in BNNN, whose target depends on V0
+    pub computed_jump: bool,
+    // Contains an FX55 or FX33 that provably writes over reachable code
+    pub writes_code: bool,
+}
+
+// Instructions reachable from 0x200, grouped into basic blocks, plus what the
+// walk could not follow
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<u16, BasicBlock>,
+    // Entry points of every 2NNN target
+    pub subroutines: BTreeSet<u16>,
+    // Instruction addresses of BNNN jumps
+    pub computed_jumps: Vec<u16>,
+    // (instruction address, first byte written) of writes into code
+    pub code_writes: Vec<(u16, u16)>,
+    // Inclusive ROM ranges no reachable instruction covers. These are either
+    // data (sprites, tables) or code only reached through BNNN.
+    pub unreachable: Vec<(u16, u16)>,
+}
+
+fn fetch(rom: &[u8], address: u16) -> Option<u16> {
+    let offset = address.checked_sub(ENTRY)? as usize;
+    match (rom.get(offset), rom.get(offset + 1)) {
+        (Some(high), Some(low)) => Some((*high as u16) << 8 | *low as u16),
+        _ => None,
+    }
+}
+
+// Where execution can go after the instruction at `address`. Computed jumps
+// and returns have no static successors.
+fn successors(address: u16, opcode: u16) -> Vec<(u16, EdgeKind)> {
+    let next = address + 2;
+    let nnn = opcode & 0x0FFF;
+
+    match opcode & 0xF000 {
+        0x0000 if opcode == 0x00EE => vec![],
+        0x1000 => vec![(nnn, EdgeKind::Jump)],
+        0x2000 => vec![(nnn, EdgeKind::Call), (next, EdgeKind::Fallthrough)],
+        0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xE000 => vec![(next, EdgeKind::Fallthrough), (next + 2, EdgeKind::Skip)],
+        0xB000 => vec![],
+        _ => vec![(next, EdgeKind::Fallthrough)],
+    }
+}
+
+fn ends_block(opcode: u16) -> bool {
+    matches!(opcode & 0xF000, 0x1000 | 0x2000 | 0x3000 | 0x4000 | 0x5000 | 0x9000 | 0xB000 | 0xE000) || opcode == 0x00EE
+}
+
+// Walks a ROM loaded at 0x200, following jumps, calls and both sides of skips.
+// Ranges the symbols mark as data are never treated as code. Anything past
+// the end of memory is ignored, as it could never be loaded.
+pub fn analyze(rom: &[u8], symbols: &SymbolTable) -> ControlFlowGraph {
+    let rom = &rom[..rom.len().min(MAX_ROM_SIZE)];
+
+    // Find every reachable instruction
+    let mut instructions: BTreeMap<u16, u16> = BTreeMap::new();
+    let mut leaders: BTreeSet<u16> = BTreeSet::new();
+    let mut subroutines: BTreeSet<u16> = BTreeSet::new();
+    let mut worklist: Vec<u16> = vec![ENTRY];
+    leaders.insert(ENTRY);
+
+    while let Some(address) = worklist.pop() {
+        if instructions.contains_key(&address) || symbols.is_data(address) {
+            continue;
+        }
+        let opcode = match fetch(rom, address) {
+            Some(opcode) => opcode,
+            None => continue,
+        };
+        instructions.insert(address, opcode);
+
+        for (target, kind) in successors(address, opcode) {
+            if kind == EdgeKind::Call {
+                subroutines.insert(target);
+            }
+            if ends_block(opcode) {
+                leaders.insert(target);
+            }
+            worklist.push(target);
+        }
+    }
+
+    // Split them into basic blocks
+    let mut blocks: BTreeMap<u16, BasicBlock> = BTreeMap::new();
+    let mut computed_jumps: Vec<u16> = Vec::new();
+    let mut code_writes: Vec<(u16, u16)> = Vec::new();
+
+    for leader in leaders.iter() {
+        if !instructions.contains_key(leader) {
+            continue;
+        }
+
+        let mut address = *leader;
+        let mut block = BasicBlock {
+            start: address,
+            end: address,
+            successors: Vec::new(),
+            computed_jump: false,
+            writes_code: false,
+        };
+        // I as set by ANNN earlier in this block, if nothing has changed it since
+        let mut index: Option<u16> = None;
+
+        loop {
+            let opcode = instructions[&address];
+            block.end = address;
+
+            let written = match opcode & 0xF0FF {
+                0xF055 => Some(((opcode >> 8) & 0xF) + 1),
+                0xF033 => Some(3),
+                _ => None,
+            };
+            if let (Some(start), Some(length)) = (index, written) {
+                let hits_code = (start..start + length).any(|byte| {
+                    instructions.contains_key(&byte) || instructions.contains_key(&byte.wrapping_sub(1))
+                });
+                if hits_code {
+                    block.writes_code = true;
+                    code_writes.push((address, start));
+                }
+            }
+
+            index = match opcode & 0xF000 {
+                0xA000 => Some(opcode & 0x0FFF),
+                0xF000 if matches!(opcode & 0x00FF, 0x1E | 0x29 | 0x55 | 0x65) => None,
+                _ => index,
+            };
+
+            if opcode & 0xF000 == 0xB000 {
+                block.computed_jump = true;
+                computed_jumps.push(address);
+            }
+
+            let next = address + 2;
+            if ends_block(opcode) || leaders.contains(&next) || !instructions.contains_key(&next) {
+                block.successors = successors(address, opcode);
+                break;
+            }
+            address = next;
+        }
+
+        blocks.insert(block.start, block);
+    }
+
+    // Anything in the ROM no reachable instruction covers
+    let mut unreachable: Vec<(u16, u16)> = Vec::new();
+    let mut covered: BTreeSet<u16> = BTreeSet::new();
+    for address in instructions.keys() {
+        covered.insert(*address);
+        covered.insert(*address + 1);
+    }
+    let mut range_start: Option<u16> = None;
+    let rom_end = ENTRY as usize + rom.len();
+    for address in ENTRY..rom_end as u16 {
+        match (covered.contains(&address), range_start) {
+            (false, None) => range_start = Some(address),
+            (true, Some(start)) => {
+                unreachable.push((start, address - 1));
+                range_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = range_start {
+        unreachable.push((start, (rom_end - 1) as u16));
+    }
+
+    ControlFlowGraph {
+        blocks,
+        subroutines,
+        computed_jumps,
+        code_writes,
+        unreachable,
+    }
+}
+
+// Mnemonics in the style of Cowgod's reference, with addresses named from the
+// symbol table
+pub fn disassemble(opcode: u16, symbols: &SymbolTable) -> String {
+    let x = (opcode >> 8) & 0xF;
+    let y = (opcode >> 4) & 0xF;
+    let n = opcode & 0xF;
+    let nn = opcode & 0xFF;
+    let nnn = opcode & 0xFFF;
+
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => String::from("CLS"),
+            0x00EE => String::from("RET"),
+            _ => format!("SYS {}", symbols.name(nnn)),
+        },
+        0x1000 => format!("JP {}", symbols.name(nnn)),
+        0x2000 => format!("CALL {}", symbols.name(nnn)),
+        0x3000 => format!("SE V{:X}, {:#04x}", x, nn),
+        0x4000 => format!("SNE V{:X}, {:#04x}", x, nn),
+        0x5000 => format!("SE V{:X}, V{:X}", x, y),
+        0x6000 => format!("LD V{:X}, {:#04x}", x, nn),
+        0x7000 => format!("ADD V{:X}, {:#04x}", x, nn),
+        0x8000 => match n {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => format!("DW {:#06x}", opcode),
+        },
+        0x9000 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA000 => format!("LD I, {}", symbols.name(nnn)),
+        0xB000 => format!("JP V0, {}", symbols.name(nnn)),
+        0xC000 => format!("RND V{:X}, {:#04x}", x, nn),
+        0xD000 => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        0xE000 => match nn {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => format!("DW {:#06x}", opcode),
+        },
+        _ => match nn {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            _ => format!("DW {:#06x}", opcode),
+        },
+    }
+}
+
+impl ControlFlowGraph {
+    // Graphviz output. Each subroutine, and the code reached from 0x200, is a
+    // cluster; computed jumps are red and blocks that write into code orange.
+    pub fn to_dot(&self, rom: &[u8], symbols: &SymbolTable) -> String {
+        let mut output = String::new();
+        output.push_str("digraph rom {\n");
+        output.push_str("  node [shape=box, fontname=\"monospace\"];\n");
+
+        // Give each block to the first routine that reaches it without
+        // following calls
+        let mut owner: BTreeMap<u16, u16> = BTreeMap::new();
+        let routines: Vec<u16> = std::iter::once(ENTRY).chain(self.subroutines.iter().copied()).collect();
+        for routine in routines.iter() {
+            let mut worklist: Vec<u16> = vec![*routine];
+            while let Some(start) = worklist.pop() {
+                let block = match self.blocks.get(&start) {
+                    Some(block) => block,
+                    None => continue,
+                };
+                if owner.contains_key(&start) {
+                    continue;
+                }
+                owner.insert(start, *routine);
+                for (target, kind) in block.successors.iter() {
+                    if *kind != EdgeKind::Call {
+                        worklist.push(*target);
+                    }
+                }
+            }
+        }
+
+        for routine in routines.iter() {
+            let label = match symbols.label(*routine) {
+                Some(name) => name.to_string(),
+                None if *routine == ENTRY => String::from("main"),
+                None => format!("sub_{:03x}", routine),
+            };
+            output.push_str(&format!("  subgraph cluster_{:03x} {{\n", routine));
+            output.push_str(&format!("    label=\"{}\";\n", label));
+
+            for block in self.blocks.values().filter(|block| owner.get(&block.start) == Some(routine)) {
+                let mut lines = String::new();
+                for address in (block.start..=block.end).step_by(2) {
+                    if let Some(name) = symbols.label(address) {
+                        lines.push_str(&format!("{}:\\l", name));
+                    }
+                    if let Some(opcode) = fetch(rom, address) {
+                        lines.push_str(&format!("{:03x}: {}\\l", address, disassemble(opcode, symbols)));
+                    }
+                }
+
+                let color = if block.computed_jump {
+                    ", color=red"
+                } else if block.writes_code {
+                    ", color=orange"
+                } else {
+                    ""
+                };
+                output.push_str(&format!("    b{:03x} [label=\"{}\"{}];\n", block.start, lines, color));
+            }
+
+            output.push_str("  }\n");
+        }
+
+        for block in self.blocks.values() {
+            for (target, kind) in block.successors.iter() {
+                if !self.blocks.contains_key(target) {
+                    continue;
+                }
+                let style = match kind {
+                    EdgeKind::Fallthrough => "",
+                    EdgeKind::Jump => " [label=\"jump\"]",
+                    EdgeKind::Skip => " [label=\"skip\"]",
+                    EdgeKind::Call => " [label=\"call\", style=dashed]",
+                };
+                output.push_str(&format!("  b{:03x} -> b{:03x}{};\n", block.start, target, style));
+            }
+        }
+
+        if !self.unreachable.is_empty() {
+            let ranges: Vec<String> = self.unreachable.iter().map(|(start, end)| format!("{:03x}-{:03x}", start, end)).collect();
+            output.push_str(&format!("  unreachable [shape=note, label=\"unreachable\\n{}\"];\n", ranges.join("\\n")));
+        }
+
+        output.push_str("}\n");
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_split_blocks_and_follow_both_sides() {
+        // 200: SE V0, 0   202: JP 206   204: CLS   206: JP 206
+        let rom = [0x30, 0x00, 0x12, 0x06, 0x00, 0xE0, 0x12, 0x06];
+        let graph = analyze(&rom, &SymbolTable::default());
+
+        let starts: Vec<u16> = graph.blocks.keys().copied().collect();
+        assert_eq!(starts, vec![0x200, 0x202, 0x204, 0x206]);
+        assert_eq!(graph.blocks[&0x200].successors, vec![(0x202, EdgeKind::Fallthrough), (0x204, EdgeKind::Skip)]);
+        assert_eq!(graph.blocks[&0x202].successors, vec![(0x206, EdgeKind::Jump)]);
+        assert!(graph.unreachable.is_empty());
+    }
+
+    #[test]
+    fn calls_mark_subroutines() {
+        // 200: CALL 204   202: JP 202   204: RET
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x00, 0xEE];
+        let graph = analyze(&rom, &SymbolTable::default());
+
+        assert_eq!(graph.subroutines.iter().copied().collect::<Vec<u16>>(), vec![0x204]);
+        assert!(graph.blocks[&0x204].successors.is_empty());
+        assert!(graph.to_dot(&rom, &SymbolTable::default()).contains("subgraph cluster_204"));
+    }
+
+    #[test]
+    fn data_after_the_code_is_unreachable() {
+        // 200: JP 200, then a sprite
+        let rom = [0x12, 0x00, 0xF0, 0x90, 0xF0];
+        let graph = analyze(&rom, &SymbolTable::default());
+
+        assert_eq!(graph.unreachable, vec![(0x202, 0x204)]);
+    }
+
+    #[test]
+    fn computed_jumps_and_code_writes_are_reported() {
+        // 200: LD I, 200   202: LD [I], V0   204: JP V0, 300
+        let rom = [0xA2, 0x00, 0xF0, 0x55, 0xB3, 0x00];
+        let graph = analyze(&rom, &SymbolTable::default());
+
+        assert_eq!(graph.computed_jumps, vec![0x204]);
+        assert_eq!(graph.code_writes, vec![(0x202, 0x200)]);
+        assert!(graph.blocks[&0x200].writes_code);
+    }
+
+    #[test]
+    fn roms_larger_than_memory_do_not_overflow() {
+        // Long enough that 0x200 + length wraps a u16
+        let mut rom = vec![0; 0x10000];
+        rom[..2].copy_from_slice(&[0x12, 0x00]);
+        let graph = analyze(&rom, &SymbolTable::default());
+
+        assert_eq!(graph.unreachable, vec![(0x202, 0xFFF)]);
+    }
+
+    #[test]
+    fn disassembles_in_cowgod_style() {
+        assert_eq!(disassemble(0x00E0, &SymbolTable::default()), "CLS");
+        assert_eq!(disassemble(0xD125, &SymbolTable::default()), "DRW V1, V2, 5");
+        assert_eq!(disassemble(0xF165, &SymbolTable::default()), "LD V1, [I]");
+        assert_eq!(disassemble(0x5121, &SymbolTable::default()), "SE V1, V2");
+    }
+}
@@ -0,0 +1,100 @@
+use std::time;
+use crate::framebuffer::{Framebuffer, HEIGHT, WIDTH};
+
+const ITERATIONS: usize = 20_000;
+const SPRITES_PER_FRAME: usize = 32;
+
+// The byte-per-pixel, column-major display the interpreter used before
+// Framebuffer, kept as the baseline for comparison.
+struct ByteFramebuffer {
+    display: [[u8; HEIGHT]; WIDTH],
+}
+
+impl ByteFramebuffer {
+    fn clear(&mut self) {
+        self.display = [[0; HEIGHT]; WIDTH];
+    }
+
+    fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        let mut collision = false;
+
+        for (row, sprite_row) in sprite.iter().enumerate() {
+            for col in 0..8 {
+                let sprite_pixel = (sprite_row >> (7 - col)) & 1;
+                let display_pixel = &mut self.display[(x + col) % WIDTH][(y + row) % HEIGHT];
+
+                if sprite_pixel == 1 {
+                    collision |= *display_pixel == 1;
+                    *display_pixel ^= 1;
+                }
+            }
+        }
+
+        collision
+    }
+}
+
+// Sprite positions from a fixed LCG so both implementations draw the same thing
+fn positions() -> Vec<(usize, usize)> {
+    let mut state: u32 = 0x2A6;
+    (0..SPRITES_PER_FRAME)
+        .map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (((state >> 16) as usize) % WIDTH, ((state >> 8) as usize) % HEIGHT)
+        })
+        .collect()
+}
+
+fn time<F: FnMut() -> usize>(name: &str, mut f: F) -> time::Duration {
+    let start = time::Instant::now();
+    let collisions = f();
+    let elapsed = start.elapsed();
+
+    println!("{:<10} {:>10.2?}  ({} collisions)", name, elapsed, collisions);
+    elapsed
+}
+
+// Draws and clears full screens of 15-row sprites, then compares frames, with
+// the packed framebuffer and with the old byte-per-pixel layout.
+pub fn run() {
+    let sprite: [u8; 15] = [0xF0, 0x90, 0x90, 0x90, 0xF0, 0x20, 0x60, 0x20, 0x20, 0x70, 0xF0, 0x10, 0xF0, 0x80, 0xF0];
+    let positions = positions();
+
+    println!("{} frames of {} sprites", ITERATIONS, SPRITES_PER_FRAME);
+
+    let bytes = time("bytes", || {
+        let mut previous = ByteFramebuffer { display: [[0; HEIGHT]; WIDTH] };
+        let mut current = ByteFramebuffer { display: [[0; HEIGHT]; WIDTH] };
+        let mut collisions = 0;
+
+        for _ in 0..ITERATIONS {
+            current.clear();
+            for (x, y) in &positions {
+                collisions += current.draw_sprite(*x, *y, &sprite) as usize;
+            }
+            collisions += (current.display == previous.display) as usize;
+            std::mem::swap(&mut previous, &mut current);
+        }
+
+        collisions
+    });
+
+    let packed = time("packed", || {
+        let mut previous = Framebuffer::default();
+        let mut current = Framebuffer::default();
+        let mut collisions = 0;
+
+        for _ in 0..ITERATIONS {
+            current.clear();
+            for (x, y) in &positions {
+                collisions += current.draw_sprite(*x, *y, &sprite, true) as usize;
+            }
+            collisions += (current == previous) as usize;
+            std::mem::swap(&mut previous, &mut current);
+        }
+
+        collisions
+    });
+
+    println!("speedup    {:>10.1}x", bytes.as_secs_f64() / packed.as_secs_f64());
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use macroquad::prelude::*;
+use crate::chip8::MAX_ROM_SIZE;
+use crate::romdb;
+use crate::romfile;
+
+const FONT_SIZE: u16 = 22;
+const LINE_HEIGHT: f32 = 26.0;
+// Lines above the list taken by the title, help and column headings
+const HEADER_LINES: usize = 3;
+// Hex digits of the SHA-1 shown, enough to tell ROMs apart at a glance
+const HASH_DIGITS: usize = 8;
+
+// What the browser shows about each ROM
+struct RomInfo {
+    path: String,
+    name: String,
+    size: Option<usize>,
+    sha1: String,
+    platform: String,
+    title: String,
+    // Why the ROM can't be run, shown in place of its platform
+    error: Option<String>,
+}
+
+impl RomInfo {
+    // Unreadable ROMs are still listed, with the reason, so a broken file
+    // doesn't just vanish
+    fn new(dir: &str, path: &str) -> Self {
+        let name = path.strip_prefix(dir).unwrap_or(path).trim_start_matches(['/', '\\']).to_string();
+        let mut info = Self {
+            path: path.to_string(),
+            name,
+            size: None,
+            sha1: String::from("?"),
+            platform: String::from("?"),
+            title: String::new(),
+            error: None,
+        };
+
+        let rom = match romfile::read(path) {
+            Ok(rom) => rom,
+            Err(why) => {
+                info.error = Some(why.to_string());
+                return info;
+            }
+        };
+        let sha1 = sha1_smol::Sha1::from(&rom).digest().to_string();
+
+        // The database knows the exact ROM, otherwise the extension gives a
+        // guess; plain .ch8 files could be from anything
+        let settings = match romdb::lookup(&sha1, romdb::USER_DATABASE) {
+            Ok(settings) => settings,
+            Err(why) => panic!("{}", why),
+        };
+        match settings {
+            Some(settings) => {
+                info.platform = settings.platform;
+                info.title = settings.title;
+            }
+            None => {
+                let guess = romfile::rom_name(path).ok().and_then(|name| romdb::guess_from_extension(&name));
+                info.platform = guess.map(|settings| settings.platform).unwrap_or_else(|| String::from("unknown"));
+            }
+        }
+
+        if rom.len() > MAX_ROM_SIZE {
+            info.error = Some(String::from("too large to fit in memory"));
+        }
+        info.size = Some(rom.len());
+        info.sha1 = sha1;
+        info
+    }
+}
+
+// Lists the ROMs in `dir`, including those inside zip archives, with their
+// size, hash and platform, and lets the player choose one:
+//
+//   Up/Down, PgUp/PgDn, Home/End  move
+//   Enter                         run the selected ROM
+//   Escape                        quit
+//
+// `current` is selected to begin with, so coming back from a game lands on
+// it. ROMs that can't be read, or that crashed (`crashes` maps their paths to
+// why), are shown with the error and can't be chosen. Returns None if the
+// player quits or there is nothing to choose.
+pub async fn pick_rom(dir: &str, current: Option<&str>, crashes: &HashMap<String, String>) -> Option<String> {
+    let paths = match romfile::list(dir) {
+        Ok(paths) => paths,
+        Err(why) => panic!("{}: {}", dir, why),
+    };
+    if paths.is_empty() {
+        eprintln!("No ROMs in {}", dir);
+        return None;
+    }
+
+    let mut roms: Vec<RomInfo> = paths.iter().map(|path| RomInfo::new(dir, path)).collect();
+    for rom in roms.iter_mut() {
+        if let Some(crash) = crashes.get(&rom.path) {
+            rom.error = Some(crash.clone());
+        }
+    }
+    let mut selected: usize = current.and_then(|current| paths.iter().position(|path| path == current)).unwrap_or(0);
+    let mut first_shown: usize = 0;
+
+    // Don't let the key that opened this screen count, e.g. the Escape that
+    // left a game
+    next_frame().await;
+
+    loop {
+        let visible = ((screen_height() / LINE_HEIGHT) as usize).saturating_sub(HEADER_LINES + 1).max(1);
+
+        match get_last_key_pressed() {
+            Some(KeyCode::Escape) => return None,
+            Some(KeyCode::Enter) if roms[selected].error.is_none() => return Some(roms[selected].path.clone()),
+            Some(KeyCode::Up) => selected = selected.saturating_sub(1),
+            Some(KeyCode::Down) => selected = (selected + 1).min(roms.len() - 1),
+            Some(KeyCode::PageUp) => selected = selected.saturating_sub(visible),
+            Some(KeyCode::PageDown) => selected = (selected + visible).min(roms.len() - 1),
+            Some(KeyCode::Home) => selected = 0,
+            Some(KeyCode::End) => selected = roms.len() - 1,
+            _ => {}
+        }
+
+        if selected < first_shown {
+            first_shown = selected;
+        } else if selected >= first_shown + visible {
+            first_shown = selected + 1 - visible;
+        }
+
+        clear_background(BLACK);
+
+        // Columns are laid out in character widths so the fields line up
+        let char_width = measure_text("0", None, FONT_SIZE, 1.0).width;
+        let name_width = roms.iter().map(|rom| rom.name.chars().count()).max().unwrap_or(0).max(4) + 2;
+        let text = |text: &str, column: usize, line: usize, color: Color| {
+            draw_text(text, 20.0 + column as f32 * char_width, LINE_HEIGHT * (line + 1) as f32, FONT_SIZE as f32, color);
+        };
+        let columns = [0, name_width, name_width + 8, name_width + 8 + HASH_DIGITS + 2];
+
+        text(&format!("ROMs in {} ({})", dir, roms.len()), 0, 0, WHITE);
+        text("arrows PgUp PgDn: move    Enter: run    Escape: quit    (Escape in a game comes back here)", 0, 1, GRAY);
+        for (heading, column) in ["name", "size", "sha1", "platform"].iter().zip(columns) {
+            text(heading, column, 2, GRAY);
+        }
+
+        for (index, rom) in roms.iter().enumerate().skip(first_shown).take(visible) {
+            let line = HEADER_LINES + index - first_shown;
+            let color = if index == selected { WHITE } else { LIGHTGRAY };
+            if index == selected {
+                let y = LINE_HEIGHT * (line + 1) as f32;
+                draw_rectangle(10.0, y - LINE_HEIGHT + 6.0, screen_width() - 20.0, LINE_HEIGHT, DARKBLUE);
+            }
+
+            let size = rom.size.map(|size| size.to_string()).unwrap_or_else(|| String::from("?"));
+            let sha1: String = rom.sha1.chars().take(HASH_DIGITS).collect();
+            let platform = match (&rom.error, rom.title.is_empty()) {
+                (Some(error), _) => format!("{} - {}", rom.platform, error),
+                (None, true) => rom.platform.clone(),
+                (None, false) => format!("{} - {}", rom.platform, rom.title),
+            };
+
+            text(&rom.name, columns[0], line, color);
+            text(&format!("{:>6}", size), columns[1], line, color);
+            text(&sha1, columns[2], line, color);
+            text(&platform, columns[3], line, if rom.error.is_some() { RED } else { color });
+        }
+
+        next_frame().await;
+    }
+}
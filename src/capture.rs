@@ -0,0 +1,61 @@
+use std::{
+    fs,
+    io,
+};
+use macroquad::prelude::*;
+use crate::chip8::{Machine, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::palette::Palette;
+
+// An RGBA image of the emulator framebuffer, built from the machine state
+// rather than read back from the window, so it works without a display.
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub rgba: Vec<u8>,
+}
+
+fn color_bytes(color: Color) -> [u8; 4] {
+    [
+        (color.r * 255.0) as u8,
+        (color.g * 255.0) as u8,
+        (color.b * 255.0) as u8,
+        (color.a * 255.0) as u8,
+    ]
+}
+
+impl Frame {
+    pub fn render(machine: &Machine, scale: usize, palette: &Palette) -> Self {
+        let scale = scale.max(1);
+        let width = SCREEN_WIDTH * scale;
+        let height = SCREEN_HEIGHT * scale;
+        let background = color_bytes(palette.background());
+        let foreground = color_bytes(palette.foreground());
+
+        let mut rgba = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            let row = machine.display().row(y / scale);
+            for x in 0..width {
+                if row & (1 << (SCREEN_WIDTH - 1 - x / scale)) != 0 {
+                    rgba.extend_from_slice(&foreground);
+                } else {
+                    rgba.extend_from_slice(&background);
+                }
+            }
+        }
+
+        Self { width, height, rgba }
+    }
+
+    pub fn save_png(&self, path: &str) -> io::Result<()> {
+        let file = io::BufWriter::new(fs::File::create(path)?);
+
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let mut writer = encoder.write_header().map_err(io::Error::other)?;
+        writer.write_image_data(&self.rgba).map_err(io::Error::other)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,222 @@
+use std::{
+    fmt,
+    fs,
+    io,
+};
+use crate::files::{self, invalid};
+
+// Cheats are kept per ROM in this file, one section per ROM hash:
+//
+//   [2f1d6ad0b5e4a1...]
+//   0x2f0 = 03
+//   v3 = 09
+pub const SAVED_CHEATS: &str = "cheats.cfg";
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum CheatTarget {
+    Memory(u16),
+    Register(u8),
+}
+
+impl CheatTarget {
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.trim().to_lowercase();
+        if let Some(register) = text.strip_prefix('v') {
+            return u8::from_str_radix(register, 16).ok().filter(|register| *register < 16).map(CheatTarget::Register);
+        }
+
+        let hex = text.strip_prefix("0x").unwrap_or(&text);
+        u16::from_str_radix(hex, 16).ok().filter(|address| *address < 0x1000).map(CheatTarget::Memory)
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            CheatTarget::Memory(address) => format!("{:#05x}", address),
+            CheatTarget::Register(register) => format!("v{:x}", register),
+        }
+    }
+}
+
+// A location held at a fixed value, rewritten at the start of every frame
+#[derive(Clone, Copy)]
+pub struct Cheat {
+    pub target: CheatTarget,
+    pub value: u8,
+}
+
+impl Cheat {
+    // "0x2f0 = 03" or "v3=09", the value in hex
+    pub fn parse(text: &str) -> Option<Self> {
+        let (target, value) = text.split_once('=')?;
+        Some(Cheat {
+            target: CheatTarget::parse(target)?,
+            value: u8::from_str_radix(value.trim(), 16).ok()?,
+        })
+    }
+}
+
+// The form movie headers use, without spaces: "0x2f0=03"
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={:02x}", self.target.name(), self.value)
+    }
+}
+
+#[derive(Default)]
+pub struct CheatList {
+    cheats: Vec<Cheat>,
+    rom_sha1: String,
+}
+
+impl CheatList {
+    // Cheats that didn't come from the cheats file, such as a movie's
+    pub fn with_cheats(rom_sha1: &str, cheats: Vec<Cheat>) -> Self {
+        Self {
+            cheats,
+            rom_sha1: rom_sha1.to_string(),
+        }
+    }
+
+    pub fn load(path: &str, rom_sha1: &str) -> io::Result<Self> {
+        let mut list = Self {
+            cheats: Vec::new(),
+            rom_sha1: rom_sha1.to_string(),
+        };
+
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(list),
+            Err(e) => return Err(e),
+        };
+
+        let mut in_section = false;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                in_section = name.trim().eq_ignore_ascii_case(rom_sha1);
+                continue;
+            }
+            if !in_section {
+                continue;
+            }
+
+            match Cheat::parse(line) {
+                Some(cheat) => list.cheats.push(cheat),
+                None => return Err(invalid(format!("{}: bad cheat: {}", path, line))),
+            }
+        }
+
+        Ok(list)
+    }
+
+    // Rewrites this ROM's section, leaving other ROMs' cheats alone
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut body = String::new();
+        for cheat in self.cheats.iter() {
+            body.push_str(&format!("{} = {:02x}\n", cheat.target.name(), cheat.value));
+        }
+        files::save_section(path, &self.rom_sha1, &body)
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn find(&self, target: CheatTarget) -> Option<&Cheat> {
+        self.cheats.iter().find(|cheat| cheat.target == target)
+    }
+
+    // Freezes `target` at `value`, or unfreezes it if it already was
+    pub fn toggle(&mut self, target: CheatTarget, value: u8) {
+        match self.cheats.iter().position(|cheat| cheat.target == target) {
+            Some(position) => {
+                self.cheats.remove(position);
+            }
+            None => self.cheats.push(Cheat { target, value }),
+        }
+    }
+}
+
+// Narrows down where a game keeps a value by comparing memory between
+// searches: start a search, play until the value changes, keep the addresses
+// that changed, and so on.
+pub struct CheatSearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl CheatSearch {
+    pub fn new(memory: &[u8]) -> Self {
+        Self {
+            snapshot: memory.to_vec(),
+            candidates: (0..memory.len() as u16).collect(),
+        }
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    pub fn keep_changed(&mut self, memory: &[u8]) {
+        self.filter(memory, |old, new| old != new);
+    }
+
+    pub fn keep_unchanged(&mut self, memory: &[u8]) {
+        self.filter(memory, |old, new| old == new);
+    }
+
+    pub fn keep_equal(&mut self, memory: &[u8], value: u8) {
+        self.filter(memory, |_, new| new == value);
+    }
+
+    fn filter(&mut self, memory: &[u8], keep: impl Fn(u8, u8) -> bool) {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|address| keep(snapshot[*address as usize], memory[*address as usize]));
+        self.snapshot = memory.to_vec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::temp_path;
+
+    #[test]
+    fn cheats_read_back_what_they_print() {
+        let cheat = Cheat::parse("0x2F0 = 03").unwrap();
+        assert!(cheat.target == CheatTarget::Memory(0x2F0));
+        assert_eq!(cheat.to_string(), "0x2f0=03");
+
+        let cheat = Cheat::parse(&Cheat { target: CheatTarget::Register(3), value: 9 }.to_string()).unwrap();
+        assert!(cheat.target == CheatTarget::Register(3));
+        assert_eq!(cheat.value, 9);
+
+        assert!(Cheat::parse("vg=01").is_none());
+        assert!(Cheat::parse("0x1000=01").is_none());
+        assert!(Cheat::parse("v3").is_none());
+    }
+
+    #[test]
+    fn saving_keeps_other_roms_sections() {
+        let path = temp_path("cheats", "cfg");
+        fs::write(&path, "[aaaa]\nv1 = 05\n\n[bbbb]\n0x300 = ff\n").unwrap();
+
+        let mut list = CheatList::load(&path, "bbbb").unwrap();
+        assert_eq!(list.cheats().len(), 1);
+        list.toggle(CheatTarget::Memory(0x300), 0);
+        list.toggle(CheatTarget::Register(2), 7);
+        list.save(&path).unwrap();
+
+        let other = CheatList::load(&path, "aaaa").unwrap();
+        assert!(other.find(CheatTarget::Register(1)).is_some_and(|cheat| cheat.value == 5));
+        let saved = CheatList::load(&path, "bbbb").unwrap();
+        assert!(saved.find(CheatTarget::Memory(0x300)).is_none());
+        assert!(saved.find(CheatTarget::Register(2)).is_some_and(|cheat| cheat.value == 7));
+
+        fs::remove_file(path).unwrap();
+    }
+}
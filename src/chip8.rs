@@ -1,12 +1,33 @@
 use std::{
-    thread,
     time,
     fs,
     io,
-    io::Read,
     fmt,
 };
-use macroquad::prelude::*;
+use quad_rand::RandGenerator;
+use crate::capture::Frame;
+use crate::cheats::{self, CheatList, CheatTarget};
+use crate::files::invalid;
+use crate::framebuffer::{self, Framebuffer};
+use crate::heatmap::MemoryHeatmap;
+use crate::input::{InputSource, ManualInput, ScriptedInput};
+use crate::keymap::Keymap;
+use crate::movie::{Movie, MovieHeader, MovieRecorder};
+use crate::palette::{self, Palette};
+use crate::patch;
+use crate::profile::Profiler;
+use crate::quirks::Quirks;
+use crate::recording::{AvRecorder, GifRecorder};
+use crate::romdb;
+use crate::romfile;
+use crate::savestate::SaveState;
+use crate::symbols::SymbolTable;
+use crate::watch::FileWatcher;
+
+pub const SCREEN_WIDTH: usize = framebuffer::WIDTH;
+pub const SCREEN_HEIGHT: usize = framebuffer::HEIGHT;
+// Programs load at 0x200 and can fill the rest of memory
+pub const MAX_ROM_SIZE: usize = 4096 - 0x200;
 
 const FONTSET: [[u8; 5]; 16] = [
     [0xF0, 0x90, 0x90, 0x90, 0xF0], // 0
@@ -27,11 +48,64 @@ const FONTSET: [[u8; 5]; 16] = [
     [0xF0, 0x80, 0xF0, 0x80, 0x80], // F
 ];
 
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Status {
+    Running,
+    // The program is stuck in a loop it can never leave, e.g. a 1NNN that jumps
+    // to itself. Nothing more will execute.
+    Halted,
+    // The program ran an instruction the interpreter doesn't have, e.g. a
+    // SUPER-CHIP one. Nothing more will execute; crash() says what happened.
+    Crashed,
+}
+
+// What a tight loop can change between two passes through its backwards jump.
+// If none of it changed and the loop touched nothing outside the CPU, the next
+// pass will be identical and so will every one after it.
+#[derive(PartialEq)]
+struct LoopSnapshot {
+    target: u16,
+    registers: [u8; 16],
+    index: u16,
+    stack: [u16; 16],
+    sp: u8,
+}
+
+// Settings for a machine built with from_rom_bytes. Everything else can be
+// changed afterwards through the setters.
+#[derive(Clone)]
+pub struct Config {
+    pub quirks: Quirks,
+    pub tick_rate: u32,
+    // None seeds the RNG from the clock
+    pub seed: Option<u64>,
+    pub loop_detection: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            quirks: Quirks::default(),
+            tick_rate: romdb::default_tick_rate(),
+            seed: None,
+            loop_detection: false,
+        }
+    }
+}
+
+fn clock_seed() -> u64 {
+    time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
 pub struct Machine {
     opcode: u16,
     keypad: [bool; 16],
     memory: [u8; 4096],
-    display: [[u8; 32]; 64], // display[x][y]
+    display: Framebuffer,
+    display_dirty: bool,
     registers: [u8; 16],
     pc: u16,
     index: u16,
@@ -39,6 +113,50 @@ pub struct Machine {
     sp: u8,
     delay_timer: u8,
     sound_timer: u8,
+    rom: Vec<u8>,
+    frame: u64,
+    // Instructions executed since the ROM was loaded
+    cycles: u64,
+    seed: u64,
+    rng: RandGenerator,
+    quirks: Quirks,
+    tick_rate: u32,
+    waiting_vblank: bool,
+    // The key Fx0A saw go down, stored once it is released
+    waiting_key: Option<u8>,
+    status: Status,
+    crash: Option<String>,
+    loop_detection: bool,
+    loop_snapshot: Option<LoopSnapshot>,
+    // Whether the current loop pass read input, timers or the RNG, or wrote
+    // memory or the display
+    loop_side_effects: bool,
+    palette: Palette,
+    screenshot_scale: usize,
+    input: Box<dyn InputSource>,
+    movie_recorder: Option<MovieRecorder>,
+    // A movie is replaying, so the cheats are the ones it was recorded with
+    playing_movie: bool,
+    gif_recorder: Option<GifRecorder>,
+    av_recorder: Option<AvRecorder>,
+    profiler: Option<Profiler>,
+    heatmap: Option<MemoryHeatmap>,
+    symbols: SymbolTable,
+    cheats: CheatList,
+    patch: Option<String>,
+    rom_path: Option<String>,
+    rom_watcher: Option<FileWatcher>,
+    // Restored after every hot reload
+    reload_state: Option<SaveState>,
+    keep_keypad_on_reload: bool,
+    // Keys held through a reload, ignored until they are released
+    masked_keys: [bool; 16],
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Machine {
@@ -47,7 +165,8 @@ impl Machine {
             opcode: 0,
             keypad: [false; 16],
             memory: [0; 4096],
-            display: [[0; 32]; 64],
+            display: Framebuffer::default(),
+            display_dirty: true,
             registers: [0; 16],
             pc: 0x200,
             index: 0,
@@ -55,259 +174,591 @@ impl Machine {
             sp: 0,
             delay_timer: 0,
             sound_timer: 0,
+            rom: Vec::new(),
+            frame: 0,
+            cycles: 0,
+            seed: 0,
+            rng: RandGenerator::new(),
+            quirks: Quirks::default(),
+            tick_rate: romdb::default_tick_rate(),
+            waiting_vblank: false,
+            waiting_key: None,
+            status: Status::Running,
+            crash: None,
+            loop_detection: false,
+            loop_snapshot: None,
+            loop_side_effects: false,
+            palette: Palette::default(),
+            screenshot_scale: 8,
+            input: Box::new(ManualInput::new()),
+            movie_recorder: None,
+            playing_movie: false,
+            gif_recorder: None,
+            av_recorder: None,
+            profiler: None,
+            heatmap: None,
+            symbols: SymbolTable::default(),
+            cheats: CheatList::default(),
+            patch: None,
+            rom_path: None,
+            rom_watcher: None,
+            reload_state: None,
+            keep_keypad_on_reload: false,
+            masked_keys: [false; 16],
         }
     }
 
+    // Reads the ROM, applying the patch given with set_patch or one found
+    // next to it, and starts it with a seed from the clock
     pub fn init(&mut self, filename: String) {
-        self.load_rom(filename);
+        self.rom_path = Some(filename.clone());
+        self.set_seed(clock_seed());
+        if let Err(why) = self.read_rom(&filename).and_then(|rom| self.load_rom_bytes(&rom)) {
+            panic!("{}", why);
+        }
+    }
+
+    // A ready-to-run machine for a ROM that didn't come from a file, e.g. one
+    // embedded in a test
+    pub fn from_rom_bytes(rom: &[u8], config: Config) -> io::Result<Self> {
+        let mut machine = Self::new();
+        machine.set_quirks(config.quirks);
+        machine.set_tick_rate(config.tick_rate);
+        machine.set_loop_detection(config.loop_detection);
+        machine.set_seed(config.seed.unwrap_or_else(clock_seed));
+        machine.load_rom_bytes(rom)?;
+        Ok(machine)
+    }
+
+    // Replaces the program and starts it from the top. Settings, input and
+    // recorders are kept. A ROM too big for memory leaves the machine as it was.
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> io::Result<()> {
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(invalid(format!("ROM is {} bytes, too large to fit in memory", rom.len())));
+        }
+
+        self.clear_state();
         self.load_fontset();
+        self.copy_rom(rom.to_vec());
+        self.rng.srand(self.seed);
+        Ok(())
     }
 
-    // TODO: Any way to make this more efficient? 
-    // Possibly read file size => read whole file into buffer at once => extend memory as slice?
-    fn load_rom(&mut self, filename: String) {
-        let mut file = match fs::File::open(filename) {
-            Ok(file) => file,
-            Err(why) => panic!("{}", why),
-        };
+    // Restarts the loaded ROM: memory, CPU, timers and display go back to how
+    // loading left them and the RNG is reseeded with the same seed, so a run
+    // can be repeated exactly. The frame count, settings, input and GIF and
+    // video recorders carry on. A movie being recorded is finished here, since
+    // replaying it wouldn't know about the reset.
+    pub fn reset(&mut self) {
+        self.clear_state();
+        self.load_fontset();
+        self.copy_rom(self.rom.clone());
+        self.rng.srand(self.seed);
 
-        let mut pos = 0;
-        let mut byte: [u8; 1] = [0; 1];
+        if self.movie_recorder.take().is_some() {
+            eprintln!("Reset: stopped recording the movie");
+        }
+    }
 
-        loop {
-            match file.read_exact(&mut byte) {
-                Ok(_) => {
-                    self.memory[0x200 + pos] = byte[0];
-                }
+    // A reset that also forgets the frame count and stops recordings,
+    // profiling and reload watching, as if the machine had just been created
+    // with this ROM
+    pub fn hard_reset(&mut self) {
+        self.reset();
+        self.frame = 0;
+        self.masked_keys = [false; 16];
+        self.gif_recorder = None;
+        self.av_recorder = None;
+        self.profiler = None;
+        self.rom_watcher = None;
+        self.reload_state = None;
+    }
 
-                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {
-                    continue;
-                }
+    // An IPS or BPS patch applied to the ROM as it loads. Call before init.
+    pub fn set_patch(&mut self, path: &str) {
+        self.patch = Some(path.to_string());
+    }
 
-                Err(_) => {
-                    break;
-                }
-            }
-            pos += 1;
+    // Reloads the ROM whenever its file, or the archive holding it, changes.
+    // With `keep_keypad`, keys held during the reload keep acting; otherwise
+    // they have to be pressed again.
+    pub fn watch_rom(&mut self, keep_keypad: bool) {
+        if let Some(ref path) = self.rom_path {
+            self.rom_watcher = Some(FileWatcher::new(romfile::file_path(path)));
+            self.keep_keypad_on_reload = keep_keypad;
         }
     }
 
-    fn load_fontset(&mut self) {
-        for sprite in 0..16 {
-            for row in 0..5 {
-                self.memory[(sprite * 5) + row] = FONTSET[sprite][row];
+    // A state to put the machine back into after each hot reload, so work on
+    // e.g. the third level doesn't mean replaying the first two
+    pub fn set_reload_state(&mut self, state: Option<SaveState>) {
+        self.reload_state = state;
+    }
+
+    // Makes the current state the reload state, also saving it next to the
+    // ROM for --reload-state
+    pub fn keep_reload_state(&mut self) {
+        let state = self.save_state();
+        if let Some(ref path) = self.rom_path {
+            if let Err(why) = state.save(&SaveState::path_for_rom(path)) {
+                panic!("{}", why);
             }
         }
+        self.reload_state = Some(state);
     }
 
-    fn map_key_to_keyboard(keycode: u8) -> macroquad::input::KeyCode {
-        use macroquad::input::KeyCode;
-        match keycode {
-            0x1 => KeyCode::Key1,
-            0x2 => KeyCode::Key2,
-            0x3 => KeyCode::Key3,
-            0xC => KeyCode::Key4,
-            0x4 => KeyCode::Q,
-            0x5 => KeyCode::W,
-            0x6 => KeyCode::E,
-            0xD => KeyCode::R,
-            0x7 => KeyCode::A,
-            0x8 => KeyCode::S,
-            0x9 => KeyCode::D,
-            0xE => KeyCode::F,
-            0xA => KeyCode::Z,
-            0x0 => KeyCode::X,
-            0xB => KeyCode::C,
-            0xF => KeyCode::V,
-            _ => panic!("Incorrect key to map"),
-        }
-    }
-
-    fn map_key_from_keyboard(keycode: macroquad::input::KeyCode) -> u8 {
-        use macroquad::input::KeyCode;
-        match keycode {
-            KeyCode::Key1 => 0x1,
-            KeyCode::Key2 => 0x2,
-            KeyCode::Key3 => 0x3,
-            KeyCode::Key4 => 0xC,
-            KeyCode::Q => 0x4,
-            KeyCode::W => 0x5,
-            KeyCode::E => 0x6,
-            KeyCode::R => 0xD,
-            KeyCode::A => 0x7,
-            KeyCode::S => 0x8,
-            KeyCode::D => 0x9,
-            KeyCode::F => 0xE,
-            KeyCode::Z => 0xA,
-            KeyCode::X => 0x0,
-            KeyCode::C => 0xB, 
-            KeyCode::V => 0xF,
-            _ => panic!("Incorrect key to map"),
-        }
-    }
-
-    fn map_opcode_delay(opcode: u16) -> time::Duration {
-        let starts_with = |num, places| -> bool {
-            let mask = match places {
-                1 => 0x000F,
-                2 => 0x00FF,
-                3 => 0x0FFF,
-                4 => 0xFFFF,
-                _ => return false,
-            };
-            let value = (opcode >> (4 * (4 - places))) & mask;
-            if value == num { true } else { false }
+    pub fn save_state(&self) -> SaveState {
+        SaveState {
+            rom_sha1: self.rom_sha1(),
+            memory: self.memory.to_vec(),
+            registers: self.registers,
+            pc: self.pc,
+            index: self.index,
+            stack: self.stack,
+            sp: self.sp,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            display: self.display.rows().to_vec(),
+        }
+    }
+
+    pub fn restore_state(&mut self, state: &SaveState) {
+        self.memory.copy_from_slice(&state.memory);
+        self.registers = state.registers;
+        self.pc = state.pc;
+        self.index = state.index;
+        self.stack = state.stack;
+        self.sp = state.sp;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.display.set_rows(&state.display);
+        self.display_dirty = true;
+        self.status = Status::Running;
+        self.crash = None;
+        self.loop_snapshot = None;
+    }
+
+    // Puts the CPU, memory and display back to power-on, keeping settings
+    // and the frame counter
+    fn clear_state(&mut self) {
+        self.opcode = 0;
+        self.keypad = [false; 16];
+        self.memory = [0; 4096];
+        self.display.clear();
+        self.display_dirty = true;
+        self.registers = [0; 16];
+        self.pc = 0x200;
+        self.index = 0;
+        self.stack = [0; 16];
+        self.sp = 0;
+        self.delay_timer = 0;
+        self.sound_timer = 0;
+        self.cycles = 0;
+        self.waiting_vblank = false;
+        self.waiting_key = None;
+        self.status = Status::Running;
+        self.crash = None;
+        self.loop_snapshot = None;
+        self.loop_side_effects = false;
+    }
+
+    // Reads the ROM file again and starts it from the top, or from the reload
+    // state if one is set. The state's memory is restored first and the new
+    // ROM copied over it, so the fresh code runs with the saved variables.
+    // If the new ROM can't be loaded the old one keeps running. Like reset,
+    // this reseeds the RNG and finishes a movie being recorded; a movie being
+    // replayed wouldn't match the new ROM, so nothing is reloaded during one.
+    pub fn reload_rom(&mut self) {
+        let path = match self.rom_path.clone() {
+            Some(path) => path,
+            None => return,
         };
+        if self.playing_movie {
+            eprintln!("Not reloading {} while a movie plays", path);
+            return;
+        }
 
-        let ends_with = |num, places| -> bool {
-            let mask = match places {
-                1 => 0x000F,
-                2 => 0x00FF,
-                3 => 0x0FFF,
-                4 => 0xFFFF,
-                _ => return false,
-            };
-            let value = opcode & mask;
-            if value == num { true } else { false }
+        let rom = match self.read_rom(&path) {
+            Ok(rom) => rom,
+            Err(why) => {
+                eprintln!("Not reloading {}: {}", path, why);
+                return;
+            }
         };
 
-        // https://jackson-s.me/2019/07/13/Chip-8-Instruction-Scheduling-and-Frequency.html
-        match opcode {
-            0x00E0 => time::Duration::from_micros(109),
-            0x00EE => time::Duration::from_micros(105),
-            opcode if starts_with(0x1, 1) => time::Duration::from_micros(105),
-            opcode if starts_with(0x2, 1) => time::Duration::from_micros(105),
-            opcode if starts_with(0x3, 1) => time::Duration::from_micros(55),
-            opcode if starts_with(0x4, 1) => time::Duration::from_micros(55),
-            opcode if starts_with(0x5, 1) => time::Duration::from_micros(73),
-            opcode if starts_with(0x6, 1) => time::Duration::from_micros(27),
-            opcode if starts_with(0x7, 1) => time::Duration::from_micros(45),
-            opcode if starts_with(0x8, 1) && ends_with(0x0, 1) => time::Duration::from_micros(200),
-            opcode if starts_with(0x8, 1) && ends_with(0x1, 1) => time::Duration::from_micros(200),
-            opcode if starts_with(0x8, 1) && ends_with(0x2, 1) => time::Duration::from_micros(200),
-            opcode if starts_with(0x8, 1) && ends_with(0x3, 1) => time::Duration::from_micros(200),
-            opcode if starts_with(0x8, 1) && ends_with(0x4, 1) => time::Duration::from_micros(200),
-            opcode if starts_with(0x8, 1) && ends_with(0x5, 1) => time::Duration::from_micros(200),
-            opcode if starts_with(0x8, 1) && ends_with(0x6, 1) => time::Duration::from_micros(200),
-            opcode if starts_with(0x8, 1) && ends_with(0x7, 1) => time::Duration::from_micros(200),
-            opcode if starts_with(0x8, 1) && ends_with(0xE, 1) => time::Duration::from_micros(200),
-            opcode if starts_with(0x9, 1) => time::Duration::from_micros(73),
-            opcode if starts_with(0xA, 1) => time::Duration::from_micros(55),
-            opcode if starts_with(0xB, 1) => time::Duration::from_micros(105),
-            opcode if starts_with(0xC, 1) => time::Duration::from_micros(164),
-            opcode if starts_with(0xD, 1) => time::Duration::from_micros(22734),
-            opcode if starts_with(0xE, 1) && ends_with(0x9E, 2) => time::Duration::from_micros(73),
-            opcode if starts_with(0xE, 1) && ends_with(0xA1, 2) => time::Duration::from_micros(73),
-            opcode if starts_with(0xF, 1) && ends_with(0x07, 2) => time::Duration::from_micros(45),
-            opcode if starts_with(0xF, 1) && ends_with(0x0A, 2) => time::Duration::from_micros(0),
-            opcode if starts_with(0xF, 1) && ends_with(0x15, 2) => time::Duration::from_micros(45),
-            opcode if starts_with(0xF, 1) && ends_with(0x18, 2) => time::Duration::from_micros(45),
-            opcode if starts_with(0xF, 1) && ends_with(0x1E, 2) => time::Duration::from_micros(86),
-            opcode if starts_with(0xF, 1) && ends_with(0x29, 2) => time::Duration::from_micros(91),
-            opcode if starts_with(0xF, 1) && ends_with(0x33, 2) => time::Duration::from_micros(927),
-            opcode if starts_with(0xF, 1) && ends_with(0x55, 2) => time::Duration::from_micros(605),
-            opcode if starts_with(0xF, 1) && ends_with(0x65, 2) => time::Duration::from_micros(605),
-            _ => time::Duration::from_micros(2000),
-        }
-    }
-
-    fn process_input(&mut self) {
-        use macroquad::input::KeyCode;
-
-        if macroquad::input::is_key_down( KeyCode::Key1 ) {
-            self.keypad[0x1] = true;
-        } else {
-            self.keypad[0x1] = false;
+        let keypad = self.keypad;
+        self.clear_state();
+        self.load_fontset();
+
+        if let Some(state) = self.reload_state.clone() {
+            self.restore_state(&state);
         }
+        self.copy_rom(rom);
+        self.rng.srand(self.seed);
 
-        if macroquad::input::is_key_down( KeyCode::Key2 ) {
-            self.keypad[0x2] = true;
-        } else {
-            self.keypad[0x2] = false;
+        if self.movie_recorder.take().is_some() {
+            eprintln!("Reloaded: stopped recording the movie");
         }
 
-        if macroquad::input::is_key_down( KeyCode::Key3 ) {
-            self.keypad[0x3] = true;
+        if self.keep_keypad_on_reload {
+            self.keypad = keypad;
         } else {
-            self.keypad[0x3] = false;
+            self.masked_keys = keypad;
         }
+    }
 
-        if macroquad::input::is_key_down( KeyCode::Key4 ) {
-            self.keypad[0xC] = true;
-        } else {
-            self.keypad[0xC] = false;
+    pub fn set_seed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng.srand(seed);
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    // Number of instructions executed per frame
+    pub fn set_tick_rate(&mut self, tick_rate: u32) {
+        self.tick_rate = tick_rate.max(1);
+    }
+
+    // Also halt on loops that make no progress, not just self-jumps. Off by
+    // default since it compares registers on every backwards jump.
+    pub fn set_loop_detection(&mut self, enabled: bool) {
+        self.loop_detection = enabled;
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    // Why the program crashed, once the status is Crashed
+    pub fn crash(&self) -> Option<&str> {
+        self.crash.as_deref()
+    }
+
+    // Labels used wherever the machine reports an address
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn set_cheats(&mut self, cheats: CheatList) {
+        self.cheats = cheats;
+    }
+
+    pub fn cheats(&self) -> &CheatList {
+        &self.cheats
+    }
+
+    // Freezes `target` at its current value, or unfreezes it, and remembers
+    // the change for this ROM. Movies hold the cheats they started with, so
+    // they can't change while one is recorded or replayed.
+    pub fn toggle_cheat(&mut self, target: CheatTarget) {
+        if self.movie_recorder.is_some() || self.playing_movie {
+            eprintln!("Cheats can't change during a movie");
+            return;
         }
 
-        if macroquad::input::is_key_down( KeyCode::Q ) {
-            self.keypad[0x4] = true;
-        } else {
-            self.keypad[0x4] = false;
+        let value = match target {
+            CheatTarget::Memory(address) => self.memory[address as usize % 4096],
+            CheatTarget::Register(register) => self.registers[register as usize % 16],
+        };
+        self.cheats.toggle(target, value);
+
+        if let Err(why) = self.cheats.save(cheats::SAVED_CHEATS) {
+            panic!("{}", why);
         }
+    }
 
-        if macroquad::input::is_key_down( KeyCode::W ) {
-            self.keypad[0x5] = true;
-        } else {
-            self.keypad[0x5] = false;
+    fn apply_cheats(&mut self) {
+        for cheat in self.cheats.cheats() {
+            match cheat.target {
+                CheatTarget::Memory(address) => self.memory[address as usize % 4096] = cheat.value,
+                CheatTarget::Register(register) => self.registers[register as usize % 16] = cheat.value,
+            }
         }
+    }
 
-        if macroquad::input::is_key_down( KeyCode::E ) {
-            self.keypad[0x6] = true;
-        } else {
-            self.keypad[0x6] = false;
+    pub fn memory(&self) -> &[u8; 4096] {
+        &self.memory
+    }
+
+    pub fn write_memory(&mut self, address: u16, value: u8) {
+        self.memory[address as usize % 4096] = value;
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    pub fn set_register(&mut self, register: u8, value: u8) {
+        self.registers[register as usize % 16] = value;
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn set_index(&mut self, index: u16) {
+        self.index = index & 0x0FFF;
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.display_dirty = true;
+    }
+
+    // Switches to the next built-in palette and remembers it for this ROM
+    pub fn cycle_palette(&mut self) {
+        self.set_palette(self.palette.next());
+
+        if let Err(why) = self.palette.save(palette::SAVED_PALETTES, &self.rom_sha1()) {
+            panic!("{}", why);
         }
+    }
 
-        if macroquad::input::is_key_down( KeyCode::R ) {
-            self.keypad[0xD] = true;
-        } else {
-            self.keypad[0xD] = false;
+    // Scale used for screenshots and recordings
+    pub fn set_screenshot_scale(&mut self, scale: usize) {
+        self.screenshot_scale = scale.max(1);
+    }
+
+    pub fn screenshot_scale(&self) -> usize {
+        self.screenshot_scale
+    }
+
+    // Whether the framebuffer changed since the last call
+    pub fn take_display_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.display_dirty)
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.display.pixel(x, y)
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn display(&self) -> &Framebuffer {
+        &self.display
+    }
+
+    // Writes the framebuffer to a PNG, each CHIP-8 pixel becoming a
+    // scale x scale block in the current colours.
+    pub fn screenshot(&self, path: &str, scale: usize) -> io::Result<()> {
+        Frame::render(self, scale, &self.palette).save_png(path)
+    }
+
+    // Starts recording an animated GIF, keeping one frame in every `decimate`
+    // and stopping by itself after `limit` frames if given.
+    pub fn start_gif_recording(&mut self, path: &str, decimate: u64, limit: Option<u64>) {
+        match GifRecorder::create(path, self.screenshot_scale, &self.palette, decimate, limit) {
+            Ok(recorder) => self.gif_recorder = Some(recorder),
+            Err(why) => panic!("{}", why),
         }
+    }
 
-        if macroquad::input::is_key_down( KeyCode::A ) {
-            self.keypad[0x7] = true;
-        } else {
-            self.keypad[0x7] = false;
+    // Dropping the recorder writes the GIF trailer
+    pub fn stop_gif_recording(&mut self) {
+        self.gif_recorder = None;
+    }
+
+    pub fn recording_gif(&self) -> bool {
+        self.gif_recorder.is_some()
+    }
+
+    fn capture_gif_frame(&mut self) {
+        if let Some(mut recorder) = self.gif_recorder.take() {
+            if let Err(why) = recorder.capture(self) {
+                panic!("{}", why);
+            }
+            if !recorder.finished() {
+                self.gif_recorder = Some(recorder);
+            }
         }
+    }
 
-        if macroquad::input::is_key_down( KeyCode::S ) {
-            self.keypad[0x8] = true;
-        } else {
-            self.keypad[0x8] = false;
+    // Starts a lossless recording to `<base>.y4m` and `<base>.wav`, stopping by
+    // itself after `limit` frames if given.
+    pub fn start_av_recording(&mut self, base: &str, limit: Option<u64>) {
+        match AvRecorder::create(base, self.screenshot_scale, &self.palette, limit) {
+            Ok(recorder) => self.av_recorder = Some(recorder),
+            Err(why) => panic!("{}", why),
         }
+    }
 
-        if macroquad::input::is_key_down( KeyCode::D ) {
-            self.keypad[0x9] = true;
-        } else {
-            self.keypad[0x9] = false;
+    pub fn stop_av_recording(&mut self) {
+        self.av_recorder = None;
+    }
+
+    pub fn recording_av(&self) -> bool {
+        self.av_recorder.is_some()
+    }
+
+    fn capture_av_frame(&mut self) {
+        if let Some(mut recorder) = self.av_recorder.take() {
+            if let Err(why) = recorder.capture(self) {
+                panic!("{}", why);
+            }
+            if !recorder.finished() {
+                self.av_recorder = Some(recorder);
+            }
         }
+    }
 
-        if macroquad::input::is_key_down( KeyCode::F ) {
-            self.keypad[0xE] = true;
-        } else {
-            self.keypad[0xE] = false;
+    // Counts what executes from now on; the report goes to `path` when
+    // write_profile is called
+    pub fn start_profiling(&mut self, path: &str) {
+        self.profiler = Some(Profiler::new(path));
+    }
+
+    // Starts or stops recording memory accesses for the heatmap
+    pub fn set_heatmap(&mut self, enabled: bool) {
+        self.heatmap = if enabled { Some(MemoryHeatmap::default()) } else { None };
+    }
+
+    pub fn heatmap_mut(&mut self) -> Option<&mut MemoryHeatmap> {
+        self.heatmap.as_mut()
+    }
+
+    pub fn write_profile(&self) -> io::Result<()> {
+        match self.profiler {
+            Some(ref profiler) => fs::write(profiler.path(), profiler.report(&self.rom_sha1(), self.rom.len(), &self.symbols)),
+            None => Ok(()),
         }
+    }
 
-        if macroquad::input::is_key_down( KeyCode::Z ) {
-            self.keypad[0xA] = true;
-        } else {
-            self.keypad[0xA] = false;
+    pub fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    // Both timers count down at 60 Hz, once per frame
+    fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    pub fn rom_sha1(&self) -> String {
+        sha1_smol::Sha1::from(&self.rom).digest().to_string()
+    }
+
+    pub fn memory_sha1(&self) -> String {
+        sha1_smol::Sha1::from(&self.memory[..]).digest().to_string()
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // A short, stable summary of the CPU state for batch runs and diffs
+    pub fn state_report(&self) -> String {
+        let mut output = String::new();
+
+        output.push_str( &format!("frame: {}\n", self.frame) );
+        output.push_str( &format!("cycles: {}\n", self.cycles) );
+        output.push_str( &format!("status: {:?}\n", self.status) );
+        output.push_str( &format!("pc: {}\n", self.symbols.name(self.pc)) );
+        output.push_str( &format!("index: {}\n", self.symbols.name(self.index)) );
+        output.push_str( &format!("sp: {}\n", self.sp) );
+        for (register, value) in self.registers.iter().enumerate() {
+            output.push_str( &format!("v{:x}: {:#04x}\n", register, value) );
         }
+        output.push_str( &format!("delay_timer: {}\n", self.delay_timer) );
+        output.push_str( &format!("sound_timer: {}\n", self.sound_timer) );
+        output.push_str( &format!("memory_sha1: {}\n", self.memory_sha1()) );
 
-        if macroquad::input::is_key_down( KeyCode::X ) {
-            self.keypad[0x0] = true;
-        } else {
-            self.keypad[0x0] = false;
+        output
+    }
+
+    // Starts writing every keypad state change to a movie file. Call after init
+    // so the header captures the loaded ROM and the RNG seed.
+    pub fn record_movie(&mut self, filename: &str) {
+        let header = MovieHeader {
+            rom_sha1: self.rom_sha1(),
+            seed: self.seed,
+            tick_rate: Some(self.tick_rate),
+            quirks: self.quirks,
+            cheats: self.cheats.cheats().to_vec(),
+        };
+
+        match MovieRecorder::create(filename, &header) {
+            Ok(recorder) => self.movie_recorder = Some(recorder),
+            Err(why) => panic!("{}", why),
         }
+    }
 
-        if macroquad::input::is_key_down( KeyCode::C ) {
-            self.keypad[0xB] = true;
-        } else {
-            self.keypad[0xB] = false;
+    pub fn set_input(&mut self, input: Box<dyn InputSource>) {
+        self.input = input;
+    }
+
+    // The input's keyboard layout, if it reads a keyboard
+    pub fn keymap_mut(&mut self) -> Option<&mut Keymap> {
+        self.input.keymap_mut()
+    }
+
+    // Replays a recorded movie in place of the current input source. The RNG,
+    // tick rate, quirks and cheats are restored from the movie so the session
+    // is reproduced exactly.
+    pub fn play_movie(&mut self, filename: &str) {
+        let movie = match Movie::load(filename) {
+            Ok(movie) => movie,
+            Err(why) => panic!("{}", why),
+        };
+
+        if movie.header.rom_sha1 != self.rom_sha1() {
+            panic!("Movie {} was recorded with a different ROM", filename);
         }
 
-        if macroquad::input::is_key_down( KeyCode::V ) {
-            self.keypad[0xF] = true;
-        } else {
-            self.keypad[0xF] = false;
+        self.set_seed(movie.header.seed);
+        if let Some(tick_rate) = movie.header.tick_rate {
+            self.set_tick_rate(tick_rate);
+        }
+        self.set_quirks(movie.header.quirks);
+        self.set_cheats(CheatList::with_cheats(&movie.header.rom_sha1, movie.header.cheats));
+        self.playing_movie = true;
+        self.set_input(Box::new(ScriptedInput::new(movie.events)));
+    }
+
+    // The ROM as it will be loaded: patched, and checked to fit in memory
+    fn read_rom(&self, filename: &str) -> io::Result<Vec<u8>> {
+        let mut rom = romfile::read(filename)?;
+
+        if let Some(path) = self.patch.clone().or_else(|| patch::sidecar_for_rom(filename)) {
+            rom = patch::apply_file(&rom, &path)?;
+        }
+
+        if rom.len() > MAX_ROM_SIZE {
+            return Err(invalid(format!("{} is {} bytes, too large to fit in memory", filename, rom.len())));
+        }
+
+        Ok(rom)
+    }
+
+    fn copy_rom(&mut self, rom: Vec<u8>) {
+        if rom.len() > MAX_ROM_SIZE {
+            panic!("ROM is {} bytes, too large to fit in memory", rom.len());
+        }
+
+        self.memory[0x200..0x200 + rom.len()].copy_from_slice(&rom);
+        self.rom = rom;
+    }
+
+    fn load_fontset(&mut self) {
+        for sprite in 0..16 {
+            for row in 0..5 {
+                self.memory[(sprite * 5) + row] = FONTSET[sprite][row];
+            }
         }
     }
 
@@ -321,6 +772,17 @@ impl Machine {
 
         self.pc += 2;
 
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.record_fetch(pc as u16, opcode);
+        }
+        if let Some(ref mut heatmap) = self.heatmap {
+            heatmap.record_fetch(pc as u16);
+        }
+
+        if self.loop_detection && Self::has_side_effects(opcode) {
+            self.loop_side_effects = true;
+        }
+
         let starts_with = |num, places| -> bool {
             let mask = match places {
                 1 => 0x000F,
@@ -380,45 +842,94 @@ impl Machine {
             opcode if starts_with(0xF, 1) && ends_with(0x33, 2) => self.op_Fx33(),
             opcode if starts_with(0xF, 1) && ends_with(0x55, 2) => self.op_Fx55(),
             opcode if starts_with(0xF, 1) && ends_with(0x65, 2) => self.op_Fx65(),
-            _ => panic!("Invalid opcode"),
+            _ => {
+                self.status = Status::Crashed;
+                self.crash = Some(format!("Invalid opcode {:#06x} at {}", opcode, self.symbols.name(pc as u16)));
+            }
         };
 
-        let duration = Self::map_opcode_delay(self.opcode);
-        thread::sleep(duration);
+        // Only jumps form loops; calls and returns go backwards too
+        if let Some(ref mut profiler) = self.profiler {
+            if starts_with(0x1, 1) || starts_with(0xB, 1) {
+                profiler.record_jump(pc as u16, self.pc);
+            }
+        }
+
+        self.cycles += 1;
+    }
+
+    // Instructions whose outcome depends on, or changes, something other than
+    // the registers a loop snapshot compares
+    fn has_side_effects(opcode: u16) -> bool {
+        match opcode & 0xF000 {
+            0x0000 | 0xC000 | 0xD000 | 0xE000 => true,
+            0xF000 => matches!(opcode & 0x00FF, 0x07 | 0x0A | 0x18 | 0x33 | 0x55),
+            _ => false,
+        }
     }
 
-    pub async fn run(&mut self) {
-        let scale_ratio: f32 = 16.0;
-        request_new_screen_size(64.0 * scale_ratio, 32.0 * scale_ratio);
+    // Called on every backwards jump when loop detection is on
+    fn check_loop(&mut self, target: u16) {
+        let snapshot = LoopSnapshot {
+            target,
+            registers: self.registers,
+            index: self.index,
+            stack: self.stack,
+            sp: self.sp,
+        };
 
-        loop {
-            clear_background(BLACK);
+        if !self.loop_side_effects && self.loop_snapshot.as_ref() == Some(&snapshot) {
+            self.status = Status::Halted;
+        }
 
-            self.process_input();
+        self.loop_snapshot = Some(snapshot);
+        self.loop_side_effects = false;
+    }
 
-            self.cycle();
+    // Runs one 60 Hz frame: reads the input source, executes up to tick_rate
+    // instructions and feeds any active recorders. Frontends call this once
+    // per frame and then draw the framebuffer however they like.
+    pub fn step_frame(&mut self) {
+        if self.rom_watcher.as_mut().is_some_and(|watcher| watcher.changed()) {
+            self.reload_rom();
+        }
 
-            for x in 0..64 {
-                for y in 0..32 {
-                    if self.display[x][y] != 0 {
-                        let pw: f32 = (screen_width() as f32) / 64.0;
-                        let ph: f32 = (screen_height() as f32) / 32.0;
-            
-                        draw_rectangle(pw * (x as f32), ph * (y as f32), pw as f32, ph as f32, WHITE);
-                    }
-                }
+        self.keypad = self.input.poll(self.frame);
+        for (pressed, masked) in self.keypad.iter_mut().zip(self.masked_keys.iter_mut()) {
+            *masked &= *pressed;
+            *pressed &= !*masked;
+        }
+
+        if let Some(ref mut recorder) = self.movie_recorder {
+            if let Err(why) = recorder.record(self.frame, &self.keypad) {
+                panic!("{}", why);
             }
+        }
 
-            next_frame().await;
+        self.apply_cheats();
+
+        self.waiting_vblank = false;
+        for _ in 0..self.tick_rate {
+            if self.status != Status::Running {
+                break;
+            }
+            self.cycle();
+            if self.waiting_vblank {
+                break;
+            }
         }
+
+        self.capture_gif_frame();
+        self.capture_av_frame();
+
+        self.tick_timers();
+
+        self.frame += 1;
     }
 
     fn op_00e0(&mut self) {
-        for x in 0..64 {
-            for y in 0..32 {
-                self.display[x][y] = 0x00;
-            }
-        }
+        self.display.clear();
+        self.display_dirty = true;
     }
 
     fn op_00ee(&mut self) {
@@ -429,6 +940,12 @@ impl Machine {
     fn op_1nnn(&mut self) {
         let addr: u16 = self.opcode & 0x0FFF;
 
+        if addr == self.pc - 2 {
+            self.status = Status::Halted;
+        } else if self.loop_detection && addr < self.pc {
+            self.check_loop(addr);
+        }
+
         self.pc = addr;
     }
 
@@ -495,6 +1012,10 @@ impl Machine {
         let vy: usize = ((self.opcode >> 4) & 0x000F) as usize;
 
         self.registers[vx] |= self.registers[vy];
+
+        if self.quirks.logic {
+            self.registers[0xF] = 0;
+        }
     }
 
     fn op_8xy2(&mut self) {
@@ -502,6 +1023,10 @@ impl Machine {
         let vy: usize = ((self.opcode >> 4) & 0x000F) as usize;
 
         self.registers[vx] &= self.registers[vy];
+
+        if self.quirks.logic {
+            self.registers[0xF] = 0;
+        }
     }
 
     fn op_8xy3(&mut self) {
@@ -509,6 +1034,10 @@ impl Machine {
         let vy: usize = ((self.opcode >> 4) & 0x000F) as usize;
 
         self.registers[vx] ^= self.registers[vy];
+
+        if self.quirks.logic {
+            self.registers[0xF] = 0;
+        }
     }
 
     fn op_8xy4(&mut self) {
@@ -541,6 +1070,11 @@ impl Machine {
 
     fn op_8xy6(&mut self) {
         let vx: usize = ((self.opcode >> 8) & 0x000F) as usize;
+        let vy: usize = ((self.opcode >> 4) & 0x000F) as usize;
+
+        if !self.quirks.shift {
+            self.registers[vx] = self.registers[vy];
+        }
     
         if self.registers[vx] & 0x01 != 0 {
             self.registers[0xF] = 1;
@@ -566,6 +1100,11 @@ impl Machine {
 
     fn op_8xyE(&mut self) {
         let vx: usize = ((self.opcode >> 8) & 0x000F) as usize;
+        let vy: usize = ((self.opcode >> 4) & 0x000F) as usize;
+
+        if !self.quirks.shift {
+            self.registers[vx] = self.registers[vy];
+        }
     
         if self.registers[vx] & 0x80 != 0 {
             self.registers[0xF] = 1;
@@ -593,15 +1132,16 @@ impl Machine {
 
     fn op_Bnnn(&mut self) {
         let addr: u16 = self.opcode & 0x0FFF;
+        let vx: usize = if self.quirks.jump { ((self.opcode >> 8) & 0x000F) as usize } else { 0x0 };
 
-        self.pc = addr + (self.registers[0x0] as u16);
+        self.pc = addr + (self.registers[vx] as u16);
     }
 
     fn op_Cxnn(&mut self) {
         let vx: usize = ((self.opcode >> 8) & 0x000F) as usize;
         let nn: u8 = (self.opcode & 0x00FF) as u8;
 
-        let rand_byte = macroquad::rand::gen_range(0, 255);
+        let rand_byte = self.rng.gen_range(0, 255);
 
         self.registers[vx] = rand_byte & nn;
     }
@@ -615,22 +1155,24 @@ impl Machine {
     
         let height: usize = (self.opcode & 0x000F) as usize;
     
-        self.registers[0xF] = 0;
-    
-        for row in 0..height {
-            let sprite_row = self.memory[(self.index + row as u16) as usize];
-    
-            for col in 0..8 {
-                let sprite_pixel = (sprite_row >> (7 - col)) & 1;
-                let display_pixel = &mut self.display[(x + col) % 64][(y + row) % 32];
-    
-                if sprite_pixel == 1 {
-                    if *display_pixel == 1 {
-                        self.registers[0xF] = 1;
-                    }
-                    *display_pixel ^= 1;
-                }
-            }
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.record_sprite_read(self.index, height);
+        }
+        if let Some(ref mut heatmap) = self.heatmap {
+            heatmap.record_read(self.index, height);
+        }
+
+        let mut sprite: [u8; 15] = [0; 15];
+        for (row, byte) in sprite.iter_mut().enumerate().take(height) {
+            *byte = self.memory[(self.index as usize + row) % 4096];
+        }
+
+        let collision = self.display.draw_sprite(x, y, &sprite[..height], self.quirks.wrap);
+        self.registers[0xF] = collision as u8;
+        self.display_dirty = true;
+
+        if self.quirks.vblank {
+            self.waiting_vblank = true;
         }
     }
 
@@ -659,12 +1201,23 @@ impl Machine {
 
     fn op_Fx0A(&mut self) {
         let vx: usize = ((self.opcode >> 8) & 0x000F) as usize;
-        let keycode: u8 = self.registers[vx];
 
-        // Blocks and polls input at 500 Hz
-        while self.keypad[keycode as usize] == false {
-            thread::sleep(time::Duration::from_millis(2));
+        // Like the COSMAC VIP, wait for a key to go down and store it once it
+        // is released, so one press isn't read again by the next Fx0A
+        match self.waiting_key {
+            Some(key) if !self.keypad[key as usize] => {
+                self.registers[vx] = key;
+                self.waiting_key = None;
+                return;
+            }
+            Some(_) => {}
+            None => self.waiting_key = self.keypad.iter().position(|pressed| *pressed).map(|key| key as u8),
         }
+
+        // The keypad only changes between frames, so rather than blocking,
+        // repeat this instruction next frame
+        self.pc -= 2;
+        self.waiting_vblank = true;
     }
 
     fn op_Fx15(&mut self) {
@@ -698,7 +1251,11 @@ impl Machine {
     fn op_Fx33(&mut self) {
         let vx: usize = ((self.opcode >> 8) & 0x000F) as usize;
         let value: u8 = self.registers[vx];
-        
+
+        if let Some(ref mut heatmap) = self.heatmap {
+            heatmap.record_write(self.index, 3);
+        }
+
         self.memory[(self.index) as usize] = (value / 100) % 10;
         self.memory[(self.index + 1) as usize] = (value / 10) % 10;
         self.memory[(self.index + 2) as usize] = value % 10;
@@ -707,17 +1264,40 @@ impl Machine {
     fn op_Fx55(&mut self) {
         let vx: usize = ((self.opcode >> 8) & 0x000F) as usize;
 
+        if let Some(ref mut heatmap) = self.heatmap {
+            heatmap.record_write(self.index, vx + 1);
+        }
+
         for reg in 0..=vx {
             self.memory[(self.index as usize + reg) as usize] = self.registers[reg];
         }
+
+        self.advance_index_after_memory_op(vx);
+    }
+
+    fn advance_index_after_memory_op(&mut self, vx: usize) {
+        if self.quirks.memory_increment_by_x {
+            self.index += vx as u16;
+        } else if !self.quirks.memory_leave_i_unchanged {
+            self.index += vx as u16 + 1;
+        }
     }
 
     fn op_Fx65(&mut self) {
         let vx: usize = ((self.opcode >> 8) & 0x000F) as usize;
 
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.record_data_read(self.index, vx + 1);
+        }
+        if let Some(ref mut heatmap) = self.heatmap {
+            heatmap.record_read(self.index, vx + 1);
+        }
+
         for reg in 0..=vx {
             self.registers[reg] = self.memory[(self.index as usize + reg) as usize];
         }
+
+        self.advance_index_after_memory_op(vx);
     }
 }
 
@@ -728,16 +1308,22 @@ impl fmt::Display for Machine {
         output.push_str( &format!("opcode: {}\n", self.opcode) );
         output.push_str( &format!("memory: {:?}\n", self.memory) );
         output.push_str( &format!("registers: {:?}\n", self.registers) );
-        output.push_str( &format!("pc: {}\n", self.pc) );
-        output.push_str( &format!("index: {}\n", self.index) );
-        output.push_str( &format!("stack: {:?}\n", self.stack) );
+        output.push_str( &format!("pc: {}\n", self.symbols.name(self.pc)) );
+        output.push_str( &format!("index: {}\n", self.symbols.name(self.index)) );
+        let stack: Vec<String> = self.stack.iter().map(|address| self.symbols.name(*address)).collect();
+        output.push_str( &format!("stack: [{}]\n", stack.join(", ")) );
         output.push_str( &format!("sp: {}\n", self.sp) );
         output.push_str( &format!("delay_timer: {}\n", self.delay_timer) );
         output.push_str( &format!("sound_timer: {}\n", self.sound_timer) );
-
-        let display_str = String::from("display:\n");
-        for x in 0..64 {
-            output.push_str( &format!("{:?}\n", self.display[x]) );
+        output.push_str( &format!("frame: {}\n", self.frame) );
+        output.push_str( &format!("cycles: {}\n", self.cycles) );
+        output.push_str( &format!("seed: {}\n", self.seed) );
+        output.push_str( &format!("quirks: {}\n", self.quirks) );
+        output.push_str( &format!("tick_rate: {}\n", self.tick_rate) );
+
+        output.push_str("display:\n");
+        for row in self.display.rows() {
+            output.push_str( &format!("{:064b}\n", row) );
         }
 
         write!(f, "{}", output)?;
@@ -745,3 +1331,128 @@ impl fmt::Display for Machine {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::temp_path;
+
+    fn config() -> Config {
+        Config {
+            seed: Some(42),
+            ..Config::default()
+        }
+    }
+
+    fn run_until_halted(machine: &mut Machine) {
+        for _ in 0..100 {
+            if machine.status() == Status::Halted {
+                return;
+            }
+            machine.step_frame();
+        }
+        panic!("ROM never halted");
+    }
+
+    #[test]
+    fn from_rom_bytes_runs_the_rom() {
+        // V0 = 5, V0 += 3, then jump to itself
+        let mut machine = Machine::from_rom_bytes(&[0x60, 0x05, 0x70, 0x03, 0x12, 0x04], config()).unwrap();
+        run_until_halted(&mut machine);
+
+        assert_eq!(machine.registers()[0], 8);
+        assert_eq!(machine.pc(), 0x204);
+    }
+
+    #[test]
+    fn calls_backwards_are_not_loops() {
+        // Jump over a subroutine, call back into it, then jump to itself
+        let mut machine = Machine::from_rom_bytes(&[0x12, 0x04, 0x00, 0xEE, 0x22, 0x02, 0x12, 0x06], config()).unwrap();
+        machine.profiler = Some(Profiler::new("unused"));
+        run_until_halted(&mut machine);
+
+        let report = machine.profiler.as_ref().unwrap().report(&machine.rom_sha1(), 8, &machine.symbols);
+        let loops: Vec<&str> = report.lines().skip_while(|line| *line != "hot loops:").skip(1).take_while(|line| !line.is_empty()).collect();
+        assert_eq!(loops.len(), 1);
+        assert!(loops[0].trim_start().starts_with(&format!("{}-{}", machine.symbols.name(0x206), machine.symbols.name(0x206))));
+    }
+
+    #[test]
+    fn unknown_opcodes_crash_until_reset() {
+        // V0 = 1, then SUPER-CHIP's 00FE
+        let mut machine = Machine::from_rom_bytes(&[0x60, 0x01, 0x00, 0xFE], config()).unwrap();
+        machine.step_frame();
+        assert_eq!(machine.status(), Status::Crashed);
+        assert!(machine.crash().unwrap().contains("0x00fe"));
+
+        machine.step_frame();
+        assert_eq!(machine.pc(), 0x204);
+
+        machine.reset();
+        assert_eq!(machine.status(), Status::Running);
+        assert!(machine.crash().is_none());
+    }
+
+    #[test]
+    fn oversized_roms_are_refused() {
+        assert!(Machine::from_rom_bytes(&vec![0; MAX_ROM_SIZE + 1], config()).is_err());
+
+        let mut machine = Machine::from_rom_bytes(&[0x12, 0x00], config()).unwrap();
+        assert!(machine.load_rom_bytes(&vec![0; MAX_ROM_SIZE + 1]).is_err());
+        assert_eq!(machine.rom_sha1(), sha1_smol::Sha1::from([0x12, 0x00]).digest().to_string());
+    }
+
+    #[test]
+    fn reset_repeats_the_run_exactly() {
+        // Two random numbers, then jump to itself
+        let mut machine = Machine::from_rom_bytes(&[0xC0, 0xFF, 0xC1, 0xFF, 0x12, 0x04], config()).unwrap();
+        run_until_halted(&mut machine);
+        let first = machine.state_report();
+
+        machine.reset();
+        run_until_halted(&mut machine);
+        let second = machine.state_report();
+
+        // The frame count carries on, but nothing else may differ
+        let without_frame = |report: &str| report.lines().filter(|line| !line.starts_with("frame:")).collect::<Vec<_>>().join("\n");
+        assert_eq!(without_frame(&first), without_frame(&second));
+        assert!(machine.frame() > 1);
+    }
+
+    #[test]
+    fn reload_reseeds_and_finishes_the_movie() {
+        let rom_path = temp_path("reload", "ch8");
+        let movie_path = temp_path("reload", "movie");
+        // A random number, then jump to itself
+        fs::write(&rom_path, [0xC0, 0xFF, 0x12, 0x02]).unwrap();
+
+        let mut machine = Machine::new();
+        machine.init(rom_path.clone());
+        machine.set_seed(42);
+        machine.reload_rom();
+        run_until_halted(&mut machine);
+        let first = machine.registers()[0];
+
+        machine.record_movie(&movie_path);
+        machine.reload_rom();
+        assert!(machine.movie_recorder.is_none());
+        run_until_halted(&mut machine);
+        assert_eq!(machine.registers()[0], first);
+
+        fs::remove_file(rom_path).unwrap();
+        fs::remove_file(movie_path).unwrap();
+    }
+
+    #[test]
+    fn reset_finishes_the_movie() {
+        let path = temp_path("reset", "movie");
+
+        let mut machine = Machine::from_rom_bytes(&[0x12, 0x00], config()).unwrap();
+        machine.record_movie(&path);
+        machine.step_frame();
+        machine.reset();
+        assert!(machine.movie_recorder.is_none());
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,334 @@
+use macroquad::prelude::*;
+use crate::cheats::{CheatSearch, CheatTarget};
+use crate::chip8::Machine;
+
+const ROWS: u16 = 16;
+const BYTES_PER_ROW: u16 = 16;
+const FONT_SIZE: u16 = 20;
+const LINE_HEIGHT: f32 = 22.0;
+// Candidates listed beside the hex view
+const LISTED_CANDIDATES: usize = 12;
+// Index of I in the register row, after V0-VF
+const INDEX_REGISTER: u8 = 16;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Focus {
+    Memory,
+    Register(u8),
+}
+
+// What typed hex digits are for
+#[derive(Clone, Copy, PartialEq)]
+enum Entry {
+    Edit,
+    // The value for an "equal to" search
+    SearchValue,
+}
+
+// Hex view of memory and the registers, with cheats and a value scanner. The
+// game is paused while it is open, and the editor is kept while closed so a
+// search can compare memory from before and after some play. Keys:
+//
+//   Tab            switch between memory and registers
+//   arrows, PgUp/PgDn  move
+//   0-9 a-f        type a new value for the selected byte or register
+//   l              lock (freeze) the selection at its value, or unlock it
+//   n              start a search from the current memory
+//   m / u          keep addresses that changed / didn't change since last time
+//   = and a value  keep addresses holding that value
+//   g              go to the next candidate
+//   Esc            cancel typing
+pub struct MemoryEditor {
+    focus: Focus,
+    cursor: u16,
+    view_start: u16,
+    entry: Entry,
+    digits: String,
+    search: Option<CheatSearch>,
+    next_candidate: usize,
+}
+
+impl MemoryEditor {
+    pub fn new(cursor: u16) -> Self {
+        let mut editor = Self {
+            focus: Focus::Memory,
+            cursor: cursor & 0x0FFF,
+            view_start: 0,
+            entry: Entry::Edit,
+            digits: String::new(),
+            search: None,
+            next_candidate: 0,
+        };
+        editor.keep_cursor_visible();
+        editor
+    }
+
+    // Called each time the editor is shown. The cursor goes to `cursor` and
+    // the search carries on from where it was.
+    pub fn open(&mut self, cursor: u16) {
+        self.focus = Focus::Memory;
+        self.cursor = cursor & 0x0FFF;
+        self.keep_cursor_visible();
+        self.entry = Entry::Edit;
+        self.digits.clear();
+
+        // Drop anything typed while the editor was closed
+        while get_char_pressed().is_some() {}
+    }
+
+    // Starts a search from the current memory
+    pub fn new_search(&mut self, machine: &Machine) {
+        self.search = Some(CheatSearch::new(machine.memory()));
+        self.next_candidate = 0;
+    }
+
+    // Keeps the addresses that changed since the search started or was last
+    // narrowed, or with `changed` false those that didn't
+    pub fn narrow_search(&mut self, machine: &Machine, changed: bool) {
+        if let Some(ref mut search) = self.search {
+            if changed {
+                search.keep_changed(machine.memory());
+            } else {
+                search.keep_unchanged(machine.memory());
+            }
+        }
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        self.search.as_ref().map(|search| search.candidates()).unwrap_or(&[])
+    }
+
+    fn keep_cursor_visible(&mut self) {
+        let row_start = self.cursor - self.cursor % BYTES_PER_ROW;
+        if self.cursor < self.view_start {
+            self.view_start = row_start;
+        } else if self.cursor >= self.view_start + ROWS * BYTES_PER_ROW {
+            self.view_start = row_start - (ROWS - 1) * BYTES_PER_ROW;
+        }
+    }
+
+    fn move_cursor(&mut self, delta: i32) {
+        match self.focus {
+            Focus::Memory => {
+                self.cursor = (self.cursor as i32 + delta).rem_euclid(4096) as u16;
+                self.keep_cursor_visible();
+            }
+            Focus::Register(register) => {
+                let register = (register as i32 + delta.signum()).rem_euclid(INDEX_REGISTER as i32 + 1);
+                self.focus = Focus::Register(register as u8);
+            }
+        }
+        self.digits.clear();
+    }
+
+    fn entry_width(&self) -> usize {
+        match (self.entry, self.focus) {
+            (Entry::Edit, Focus::Register(INDEX_REGISTER)) => 3,
+            _ => 2,
+        }
+    }
+
+    fn commit(&mut self, machine: &mut Machine, value: u16) {
+        match (self.entry, self.focus) {
+            (Entry::SearchValue, _) => {
+                if let Some(ref mut search) = self.search {
+                    search.keep_equal(machine.memory(), value as u8);
+                }
+                self.entry = Entry::Edit;
+            }
+            (Entry::Edit, Focus::Memory) => {
+                machine.write_memory(self.cursor, value as u8);
+                self.move_cursor(1);
+            }
+            (Entry::Edit, Focus::Register(INDEX_REGISTER)) => machine.set_index(value),
+            (Entry::Edit, Focus::Register(register)) => machine.set_register(register, value as u8),
+        }
+        self.digits.clear();
+    }
+
+    pub fn update(&mut self, machine: &mut Machine) {
+        if is_key_pressed(KeyCode::Tab) {
+            self.focus = match self.focus {
+                Focus::Memory => Focus::Register(0),
+                Focus::Register(_) => Focus::Memory,
+            };
+            self.digits.clear();
+        }
+
+        let moves = [
+            (KeyCode::Left, -1),
+            (KeyCode::Right, 1),
+            (KeyCode::Up, -(BYTES_PER_ROW as i32)),
+            (KeyCode::Down, BYTES_PER_ROW as i32),
+            (KeyCode::PageUp, -((ROWS * BYTES_PER_ROW) as i32)),
+            (KeyCode::PageDown, (ROWS * BYTES_PER_ROW) as i32),
+        ];
+        for (key, delta) in moves {
+            if is_key_pressed(key) {
+                self.move_cursor(delta);
+            }
+        }
+
+        if is_key_pressed(KeyCode::Escape) {
+            self.digits.clear();
+            self.entry = Entry::Edit;
+        }
+
+        while let Some(c) = get_char_pressed() {
+            if c.is_ascii_hexdigit() {
+                self.digits.push(c);
+                if self.digits.len() == self.entry_width() {
+                    let value = u16::from_str_radix(&self.digits, 16).unwrap_or(0);
+                    self.commit(machine, value);
+                }
+                continue;
+            }
+
+            match c {
+                'l' => match self.focus {
+                    Focus::Memory => machine.toggle_cheat(CheatTarget::Memory(self.cursor)),
+                    Focus::Register(INDEX_REGISTER) => {}
+                    Focus::Register(register) => machine.toggle_cheat(CheatTarget::Register(register)),
+                },
+                'n' => self.new_search(machine),
+                'm' => self.narrow_search(machine, true),
+                'u' => self.narrow_search(machine, false),
+                '=' if self.search.is_some() => {
+                    self.entry = Entry::SearchValue;
+                    self.digits.clear();
+                }
+                'g' => {
+                    let candidate = self.search.as_ref().and_then(|search| {
+                        let candidates = search.candidates();
+                        candidates.get(self.next_candidate % candidates.len().max(1)).copied()
+                    });
+                    if let Some(address) = candidate {
+                        self.focus = Focus::Memory;
+                        self.cursor = address;
+                        self.next_candidate += 1;
+                        self.keep_cursor_visible();
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn draw(&self, machine: &Machine) {
+        draw_rectangle(0.0, 0.0, screen_width(), screen_height(), Color::new(0.0, 0.0, 0.0, 0.85));
+
+        let char_width = measure_text("0", None, FONT_SIZE, 1.0).width;
+        let text = |text: &str, column: usize, line: usize, color: Color| {
+            let x = 16.0 + column as f32 * char_width;
+            let y = 24.0 + line as f32 * LINE_HEIGHT;
+            draw_text(text, x, y, FONT_SIZE as f32, color);
+        };
+        let highlight = |column: usize, line: usize, width: usize| {
+            let x = 16.0 + column as f32 * char_width;
+            let y = 24.0 + line as f32 * LINE_HEIGHT;
+            draw_rectangle(x - 2.0, y - LINE_HEIGHT + 5.0, width as f32 * char_width + 4.0, LINE_HEIGHT, DARKBLUE);
+        };
+
+        text("MEMORY EDITOR (paused, F5 to close)", 0, 0, WHITE);
+
+        // Registers, eight to a line, then I and the PC
+        let cheats = machine.cheats();
+        for register in 0..16u8 {
+            let (column, line) = ((register as usize % 8) * 7, 1 + register as usize / 8);
+            if self.focus == Focus::Register(register) {
+                highlight(column, line, 5);
+            }
+            let locked = cheats.find(CheatTarget::Register(register)).is_some();
+            let value = format!("V{:X} {:02x}", register, machine.registers()[register as usize]);
+            text(&value, column, line, if locked { ORANGE } else { LIGHTGRAY });
+        }
+        if self.focus == Focus::Register(INDEX_REGISTER) {
+            highlight(0, 3, 7);
+        }
+        text(&format!("I {:03x}", machine.index()), 0, 3, LIGHTGRAY);
+        text(&format!("PC {}", machine.symbols().name(machine.pc())), 10, 3, LIGHTGRAY);
+
+        // Hex view: the cursor is highlighted, locked bytes are orange, search
+        // candidates blue and the current instruction green
+        let candidates = self.candidates();
+        for row in 0..ROWS {
+            let line = 5 + row as usize;
+            let row_start = self.view_start + row * BYTES_PER_ROW;
+            text(&format!("{:03x}", row_start), 0, line, GRAY);
+
+            for offset in 0..BYTES_PER_ROW {
+                let address = row_start + offset;
+                let column = 5 + offset as usize * 3;
+                if self.focus == Focus::Memory && address == self.cursor {
+                    highlight(column, line, 2);
+                }
+
+                let color = if cheats.find(CheatTarget::Memory(address)).is_some() {
+                    ORANGE
+                } else if candidates.binary_search(&address).is_ok() {
+                    SKYBLUE
+                } else if address == machine.pc() || address == machine.pc() + 1 {
+                    GREEN
+                } else {
+                    LIGHTGRAY
+                };
+                text(&format!("{:02x}", machine.memory()[address as usize]), column, line, color);
+            }
+        }
+
+        // Locked values and the search beside the hex view
+        let side = 5 + BYTES_PER_ROW as usize * 3 + 3;
+        text("locked:", side, 5, WHITE);
+        for (line, cheat) in cheats.cheats().iter().enumerate().take(ROWS as usize / 2 - 1) {
+            text(&format!("{} = {:02x}", cheat.target.name(), cheat.value), side, 6 + line, ORANGE);
+        }
+
+        if let Some(ref search) = self.search {
+            let line = 5 + ROWS as usize / 2;
+            text(&format!("search: {} left", candidates.len()), side, line, WHITE);
+            for (offset, address) in search.candidates().iter().enumerate().take(LISTED_CANDIDATES.min(ROWS as usize / 2 - 1)) {
+                let value = machine.memory()[*address as usize];
+                text(&format!("{} = {:02x}", machine.symbols().name(*address), value), side, line + 1 + offset, SKYBLUE);
+            }
+        }
+
+        let prompt = match self.entry {
+            Entry::Edit if self.digits.is_empty() => String::new(),
+            Entry::Edit => format!("> {}_", self.digits),
+            Entry::SearchValue => format!("> equal to: {}_", self.digits),
+        };
+        let line = 6 + ROWS as usize;
+        text(&prompt, 0, line, YELLOW);
+        text("Tab registers/memory  arrows PgUp PgDn move  0-f edit  l lock", 0, line + 1, GRAY);
+        text("n new search  m changed  u unchanged  = equal to  g next candidate", 0, line + 2, GRAY);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8::Config;
+
+    #[test]
+    fn a_search_narrows_across_frames_played_with_the_editor_closed() {
+        // Counts up in V0 and stores it at 0x300, forever
+        let rom = [0xA3, 0x00, 0x70, 0x01, 0xF0, 0x55, 0x12, 0x00];
+        let mut machine = Machine::from_rom_bytes(&rom, Config { seed: Some(1), ..Config::default() }).unwrap();
+        machine.step_frame();
+
+        let mut editor = MemoryEditor::new(machine.pc());
+        editor.new_search(&machine);
+        assert_eq!(editor.candidates().len(), 4096);
+
+        // Closed: the game runs, the editor and its search stay around
+        for _ in 0..3 {
+            machine.step_frame();
+        }
+        editor.narrow_search(&machine, true);
+        assert_eq!(editor.candidates(), &[0x300]);
+
+        machine.step_frame();
+        editor.narrow_search(&machine, false);
+        assert!(editor.candidates().is_empty());
+    }
+}
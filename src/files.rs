@@ -0,0 +1,99 @@
+use std::{
+    fs,
+    io,
+};
+
+// The error for a file whose contents can't be understood
+pub fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+// Settings files keep one entry per ROM, keyed by its SHA-1: either a [sha1]
+// section, as in keymap.cfg and cheats.cfg, or a single `sha1 = ...` line, as
+// in palettes.cfg. Returns the file's text without the entry for `rom_sha1`
+// and without trailing blank lines; a missing file reads as empty.
+fn without_rom(path: &str, rom_sha1: &str) -> io::Result<String> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+
+    let mut output = String::new();
+    let mut skipping = false;
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+            skipping = name.trim().eq_ignore_ascii_case(rom_sha1);
+        }
+        let rom_line = match trimmed.split_once('=') {
+            Some((key, _)) => key.trim().eq_ignore_ascii_case(rom_sha1),
+            None => false,
+        };
+        if !skipping && !rom_line {
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+
+    while output.ends_with("\n\n") {
+        output.pop();
+    }
+
+    Ok(output)
+}
+
+// Replaces the ROM's section with `body`, leaving other sections untouched.
+// An empty body removes the section.
+pub fn save_section(path: &str, rom_sha1: &str, body: &str) -> io::Result<()> {
+    let mut output = without_rom(path, rom_sha1)?;
+
+    if !body.is_empty() {
+        if !output.is_empty() {
+            output.push('\n');
+        }
+        output.push_str(&format!("[{}]\n{}", rom_sha1, body));
+    }
+
+    fs::write(path, output)
+}
+
+// Replaces the ROM's `sha1 = value` line, leaving other lines untouched
+pub fn save_line(path: &str, rom_sha1: &str, value: &str) -> io::Result<()> {
+    let mut output = without_rom(path, rom_sha1)?;
+    output.push_str(&format!("{} = {}\n", rom_sha1, value));
+    fs::write(path, output)
+}
+
+// A path in the temp directory that no other test, or test run, shares
+#[cfg(test)]
+pub fn temp_path(name: &str, extension: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("rustchip8-{}-{}.{}", name, std::process::id(), extension))
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sections_and_lines_are_replaced_in_place() {
+        let path = temp_path("sections", "cfg");
+        fs::write(&path, "[default]\n5 = W\n\n[ABCD]\n5 = Space\n\n[ffff]\n6 = Down\n").unwrap();
+
+        save_section(&path, "abcd", "5 = Enter\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[default]\n5 = W\n\n[ffff]\n6 = Down\n\n[abcd]\n5 = Enter\n");
+
+        save_section(&path, "abcd", "").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[default]\n5 = W\n\n[ffff]\n6 = Down\n");
+
+        fs::write(&path, "abcd = amber\nffff = green\n").unwrap();
+        save_line(&path, "ABCD", "lcd").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "ffff = green\nABCD = lcd\n");
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,144 @@
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+// The 64x32 monochrome display, one u64 per row. The leftmost pixel is the
+// most significant bit, so a sprite byte lines up with the screen after a
+// single shift and a whole row can be drawn, tested and compared at once.
+#[derive(Clone, PartialEq)]
+pub struct Framebuffer {
+    rows: [u64; HEIGHT],
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self { rows: [0; HEIGHT] }
+    }
+}
+
+impl Framebuffer {
+    pub fn clear(&mut self) {
+        self.rows = [0; HEIGHT];
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let row = self.rows[y % HEIGHT];
+        row & (1 << (WIDTH - 1 - x % WIDTH)) != 0
+    }
+
+    pub fn row(&self, y: usize) -> u64 {
+        self.rows[y % HEIGHT]
+    }
+
+    pub fn rows(&self) -> &[u64; HEIGHT] {
+        &self.rows
+    }
+
+    // Missing rows are left blank
+    pub fn set_rows(&mut self, rows: &[u64]) {
+        self.clear();
+        for (row, value) in self.rows.iter_mut().zip(rows) {
+            *row = *value;
+        }
+    }
+
+    // One line per row, '#' for lit pixels and '.' for unlit ones
+    pub fn to_ascii(&self) -> String {
+        let mut output = String::new();
+        for row in self.rows.iter() {
+            for x in 0..WIDTH {
+                output.push(if row & (1 << (WIDTH - 1 - x)) != 0 { '#' } else { '.' });
+            }
+            output.push('\n');
+        }
+        output
+    }
+
+    // XORs an 8-pixel-wide sprite onto the display with its top-left corner at
+    // (x, y), which must already be on screen. With `wrap` the parts that run
+    // off an edge reappear on the opposite one, otherwise they are clipped.
+    // Returns whether any lit pixel was turned off.
+    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], wrap: bool) -> bool {
+        let mut collision = false;
+
+        for (offset, byte) in sprite.iter().enumerate() {
+            let y = y + offset;
+            if y >= HEIGHT && !wrap {
+                break;
+            }
+
+            let bits = (*byte as u64) << (WIDTH - 8);
+            let bits = if wrap { bits.rotate_right(x as u32) } else { bits >> x };
+
+            let row = &mut self.rows[y % HEIGHT];
+            collision |= *row & bits != 0;
+            *row ^= bits;
+        }
+
+        collision
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sprites_xor_and_report_collisions() {
+        let mut display = Framebuffer::default();
+
+        assert!(!display.draw_sprite(4, 2, &[0xF0], false));
+        assert!(display.pixel(4, 2) && display.pixel(7, 2) && !display.pixel(8, 2));
+
+        // Overlapping the first sprite turns the shared pixels off
+        assert!(display.draw_sprite(6, 2, &[0xF0], false));
+        assert_eq!(display.row(2), 0b1100_1100 << (WIDTH - 12));
+
+        // Pixels only turned on are not a collision
+        assert!(!display.draw_sprite(0, 3, &[0x81], false));
+    }
+
+    #[test]
+    fn drawing_twice_erases() {
+        let mut display = Framebuffer::default();
+        let sprite = [0x3C, 0x42, 0x81];
+
+        display.draw_sprite(30, 10, &sprite, false);
+        assert!(display.draw_sprite(30, 10, &sprite, false));
+        assert!(display == Framebuffer::default());
+    }
+
+    #[test]
+    fn sprites_wrap_or_clip_at_the_edges() {
+        let mut wrapped = Framebuffer::default();
+        wrapped.draw_sprite(60, 31, &[0xFF, 0xFF], true);
+        assert!(wrapped.pixel(63, 31) && wrapped.pixel(0, 31) && wrapped.pixel(3, 31));
+        assert!(wrapped.pixel(60, 0) && wrapped.pixel(3, 0));
+
+        let mut clipped = Framebuffer::default();
+        clipped.draw_sprite(60, 31, &[0xFF, 0xFF], false);
+        assert!(clipped.pixel(63, 31) && !clipped.pixel(0, 31));
+        assert_eq!(clipped.row(0), 0);
+    }
+
+    #[test]
+    fn ascii_shows_lit_pixels() {
+        let mut display = Framebuffer::default();
+        display.draw_sprite(0, 0, &[0xA0], false);
+
+        let ascii = display.to_ascii();
+        assert_eq!(ascii.lines().count(), HEIGHT);
+        assert!(ascii.starts_with("#.#."));
+        assert!(ascii.lines().all(|line| line.len() == WIDTH));
+    }
+
+    #[test]
+    fn set_rows_blanks_missing_rows() {
+        let mut display = Framebuffer::default();
+        display.draw_sprite(0, 5, &[0xFF], false);
+        display.set_rows(&[1, 2]);
+
+        assert_eq!(display.row(0), 1);
+        assert_eq!(display.row(1), 2);
+        assert_eq!(display.row(5), 0);
+    }
+}
@@ -0,0 +1,89 @@
+use std::{
+    fs,
+    io,
+};
+use crate::chip8::{Machine, Status};
+
+// Instructions run without --cycles, so a program that never halts, such as
+// one waiting for a key nobody presses, still finishes. That is minutes of
+// play at 1000 instructions a frame and hours at 15, but seconds of work.
+const DEFAULT_CYCLES: u64 = 10_000_000;
+
+// Options for `run-headless`. Anything not recognised here is left for the
+// shared command-line options (ROM path, --play, --record-gif, ...).
+pub struct HeadlessOptions {
+    pub cycles: Option<u64>,
+    pub keys: Option<String>,
+    pub dump_frame: Option<String>,
+    pub expect_frame: Option<String>,
+}
+
+impl HeadlessOptions {
+    pub fn parse(args: Vec<String>) -> (Self, Vec<String>) {
+        let mut cycles: Option<u64> = None;
+        let mut keys: Option<String> = None;
+        let mut dump_frame: Option<String> = None;
+        let mut expect_frame: Option<String> = None;
+        let mut rest: Vec<String> = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--cycles" => cycles = args.next().and_then(|cycles| cycles.parse().ok()),
+                "--keys" => keys = args.next(),
+                "--dump-frame" => dump_frame = args.next(),
+                "--expect-frame" => expect_frame = args.next(),
+                _ => rest.push(arg),
+            }
+        }
+
+        (Self { cycles, keys, dump_frame, expect_frame }, rest)
+    }
+}
+
+// Runs whole frames as fast as possible until the program halts or at least
+// --cycles instructions, DEFAULT_CYCLES if not given, have executed. Then
+// writes the requested outputs and prints the CPU state.
+// Returns the process exit code: 0, or 1 if the program crashed or the final
+// frame doesn't match the ASCII art in --expect-frame.
+pub fn run(machine: &mut Machine, options: &HeadlessOptions) -> io::Result<i32> {
+    let cycles = options.cycles.unwrap_or(DEFAULT_CYCLES);
+    while machine.status() == Status::Running && machine.cycles() < cycles {
+        machine.step_frame();
+    }
+    if machine.status() == Status::Running && options.cycles.is_none() {
+        eprintln!("Stopped after {} cycles; use --cycles to run longer", machine.cycles());
+    }
+
+    // Finish any recordings and the profile so their files are complete
+    machine.stop_gif_recording();
+    machine.stop_av_recording();
+    machine.write_profile()?;
+
+    let ascii = machine.display().to_ascii();
+
+    if let Some(ref path) = options.dump_frame {
+        if path.to_lowercase().ends_with(".png") {
+            machine.screenshot(path, machine.screenshot_scale())?;
+        } else {
+            fs::write(path, &ascii)?;
+        }
+    }
+
+    print!("{}", machine.state_report());
+
+    if let Some(crash) = machine.crash() {
+        eprintln!("{}", crash);
+        return Ok(1);
+    }
+
+    if let Some(ref path) = options.expect_frame {
+        let expected = fs::read_to_string(path)?;
+        if expected.lines().ne(ascii.lines()) {
+            println!("frame differs from {}", path);
+            return Ok(1);
+        }
+    }
+
+    Ok(0)
+}
@@ -0,0 +1,138 @@
+use macroquad::prelude::*;
+
+// Each memory byte is one cell, 64 bytes to a row
+const COLUMNS: usize = 64;
+const ROWS: usize = 4096 / COLUMNS;
+
+// Fraction of the glow kept from one frame to the next
+const DECAY: f32 = 0.9;
+
+const FETCH_COLOR: Color = Color::new(0.2, 1.0, 0.2, 1.0);
+const READ_COLOR: Color = Color::new(0.3, 0.5, 1.0, 1.0);
+const WRITE_COLOR: Color = Color::new(1.0, 0.25, 0.2, 1.0);
+
+// Per-byte glow for instruction fetches, reads and writes. Accesses light a
+// cell up fully and it fades out over the following frames, so code shows up
+// green, sprite tables blue and save areas red.
+pub struct MemoryHeatmap {
+    fetches: Vec<f32>,
+    reads: Vec<f32>,
+    writes: Vec<f32>,
+}
+
+impl Default for MemoryHeatmap {
+    fn default() -> Self {
+        Self {
+            fetches: vec![0.0; 4096],
+            reads: vec![0.0; 4096],
+            writes: vec![0.0; 4096],
+        }
+    }
+}
+
+impl MemoryHeatmap {
+
+    pub fn record_fetch(&mut self, address: u16) {
+        Self::light(&mut self.fetches, address, 2);
+    }
+
+    pub fn record_read(&mut self, address: u16, length: usize) {
+        Self::light(&mut self.reads, address, length);
+    }
+
+    pub fn record_write(&mut self, address: u16, length: usize) {
+        Self::light(&mut self.writes, address, length);
+    }
+
+    fn light(cells: &mut [f32], address: u16, length: usize) {
+        for offset in 0..length {
+            cells[(address as usize + offset) % 4096] = 1.0;
+        }
+    }
+
+    // The glow of one byte, as (fetch, read, write) from 0 to 1
+    pub fn glow(&self, address: u16) -> (f32, f32, f32) {
+        let address = address as usize % 4096;
+        (self.fetches[address], self.reads[address], self.writes[address])
+    }
+
+    // Fades every cell by one frame
+    pub fn fade(&mut self) {
+        for cells in [&mut self.fetches, &mut self.reads, &mut self.writes] {
+            for cell in cells.iter_mut() {
+                *cell *= DECAY;
+            }
+        }
+    }
+}
+
+// Draws a MemoryHeatmap in the window
+pub struct HeatmapPanel {
+    texture: Texture2D,
+    rgba: Vec<u8>,
+}
+
+impl Default for HeatmapPanel {
+    fn default() -> Self {
+        let rgba = vec![0; COLUMNS * ROWS * 4];
+        let texture = Texture2D::from_rgba8(COLUMNS as u16, ROWS as u16, &rgba);
+        texture.set_filter(FilterMode::Nearest);
+
+        Self { texture, rgba }
+    }
+}
+
+impl HeatmapPanel {
+    // Draws the heatmap into `rect` and fades it by one frame
+    pub fn draw(&mut self, heatmap: &mut MemoryHeatmap, rect: Rect) {
+        for address in 0..4096 {
+            let (fetch, read, write) = heatmap.glow(address as u16);
+            let mix = |channel: fn(Color) -> f32| {
+                let value = channel(FETCH_COLOR) * fetch + channel(READ_COLOR) * read + channel(WRITE_COLOR) * write;
+                (value.min(1.0) * 255.0) as u8
+            };
+
+            let pixel = &mut self.rgba[address * 4..address * 4 + 4];
+            pixel[0] = mix(|color| color.r);
+            pixel[1] = mix(|color| color.g);
+            pixel[2] = mix(|color| color.b);
+            pixel[3] = 255;
+        }
+        heatmap.fade();
+
+        self.texture.update_from_bytes(COLUMNS as u32, ROWS as u32, &self.rgba);
+
+        draw_rectangle(rect.x - 2.0, rect.y - 2.0, rect.w + 4.0, rect.h + 4.0, DARKGRAY);
+        let params = DrawTextureParams {
+            dest_size: Some(rect.size()),
+            ..Default::default()
+        };
+        draw_texture_ex(&self.texture, rect.x, rect.y, WHITE, params);
+
+        let legend = [("fetch", FETCH_COLOR), ("read", READ_COLOR), ("write", WRITE_COLOR)];
+        let mut x = rect.x;
+        for (label, color) in legend {
+            draw_text(label, x, rect.y + rect.h + 16.0, 18.0, color);
+            x += measure_text(label, None, 18, 1.0).width + 12.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accesses_light_up_and_fade() {
+        let mut heatmap = MemoryHeatmap::default();
+        heatmap.record_fetch(0x200);
+        heatmap.record_write(0xFFF, 2);
+
+        assert_eq!(heatmap.glow(0x201), (1.0, 0.0, 0.0));
+        // Writes past the end wrap around to the start of memory
+        assert_eq!(heatmap.glow(0x000), (0.0, 0.0, 1.0));
+
+        heatmap.fade();
+        assert_eq!(heatmap.glow(0x200), (DECAY, 0.0, 0.0));
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    cell::Cell,
+    fs,
+    io,
+    rc::Rc,
+};
+use crate::files::invalid;
+use crate::keymap::Keymap;
+use crate::movie::MovieEvent;
+
+// Supplies the keypad state to the machine once per frame. The core only ever
+// talks to this trait, so frontends decide where key presses come from.
+pub trait InputSource {
+    fn poll(&mut self, frame: u64) -> [bool; 16];
+
+    // Sources backed by a host keyboard expose their layout for remapping.
+    fn keymap_mut(&mut self) -> Option<&mut Keymap> {
+        None
+    }
+}
+
+// Reads the host keyboard through macroquad using a remappable layout.
+pub struct KeyboardInput {
+    keymap: Keymap,
+}
+
+impl KeyboardInput {
+    pub fn new(keymap: Keymap) -> Self {
+        Self { keymap }
+    }
+}
+
+impl InputSource for KeyboardInput {
+    fn poll(&mut self, _frame: u64) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        for (key, pressed) in keypad.iter_mut().enumerate() {
+            *pressed = self.keymap.keys_for(key as u8).iter().any(|keycode| macroquad::input::is_key_down(*keycode));
+        }
+        keypad
+    }
+
+    fn keymap_mut(&mut self) -> Option<&mut Keymap> {
+        Some(&mut self.keymap)
+    }
+}
+
+// Replays a timeline of keypad states, each taking effect on its frame and
+// holding until the next one. Used for movie playback.
+pub struct ScriptedInput {
+    events: Vec<MovieEvent>,
+    next_event: usize,
+    keypad: [bool; 16],
+}
+
+impl ScriptedInput {
+    pub fn new(events: Vec<MovieEvent>) -> Self {
+        Self {
+            events,
+            next_event: 0,
+            keypad: [false; 16],
+        }
+    }
+
+    // Loads a hand-written key script. Each line gives a frame number followed
+    // by the CHIP-8 keys, as hex digits, held from that frame on; a line with
+    // only a frame number releases every key. '#' starts a comment.
+    //
+    //   # hold 5 for ten frames, then 5 and A together
+    //   60 5
+    //   70 5 A
+    //   80
+    pub fn load_script(filename: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(filename)?;
+        let mut events: Vec<MovieEvent> = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let bad_line = || invalid(format!("{}:{}: bad key script line", filename, number + 1));
+
+            let line = line.split('#').next().unwrap_or("");
+            let mut fields = line.split_whitespace();
+            let frame: u64 = match fields.next() {
+                Some(frame) => frame.parse().map_err(|_| bad_line())?,
+                None => continue,
+            };
+
+            let mut keypad = [false; 16];
+            for key in fields {
+                match u8::from_str_radix(key, 16) {
+                    Ok(key) if key < 16 => keypad[key as usize] = true,
+                    _ => return Err(bad_line()),
+                }
+            }
+
+            events.push(MovieEvent { frame, keypad });
+        }
+
+        events.sort_by_key(|event| event.frame);
+        Ok(Self::new(events))
+    }
+}
+
+impl InputSource for ScriptedInput {
+    fn poll(&mut self, frame: u64) -> [bool; 16] {
+        while let Some(event) = self.events.get(self.next_event) {
+            if event.frame > frame {
+                break;
+            }
+            self.keypad = event.keypad;
+            self.next_event += 1;
+        }
+        self.keypad
+    }
+}
+
+// Keys driven from code. Clones share the same keypad, so a caller can hand one
+// clone to the machine and keep another to press and release keys.
+#[derive(Clone, Default)]
+pub struct ManualInput {
+    keypad: Rc<Cell<[bool; 16]>>,
+}
+
+impl ManualInput {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn press(&self, key: u8) {
+        self.set(key, true);
+    }
+
+    pub fn release(&self, key: u8) {
+        self.set(key, false);
+    }
+
+    fn set(&self, key: u8, pressed: bool) {
+        let mut keypad = self.keypad.get();
+        keypad[(key & 0xF) as usize] = pressed;
+        self.keypad.set(keypad);
+    }
+}
+
+impl InputSource for ManualInput {
+    fn poll(&mut self, _frame: u64) -> [bool; 16] {
+        self.keypad.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::temp_path;
+
+    fn keys(pressed: &[usize]) -> [bool; 16] {
+        let mut keypad = [false; 16];
+        for key in pressed {
+            keypad[*key] = true;
+        }
+        keypad
+    }
+
+    #[test]
+    fn manual_input_clones_share_the_keypad() {
+        let keys_held = ManualInput::new();
+        let mut source = keys_held.clone();
+
+        keys_held.press(0x5);
+        keys_held.press(0xA);
+        assert_eq!(source.poll(0), keys(&[0x5, 0xA]));
+
+        keys_held.release(0x5);
+        assert_eq!(source.poll(1), keys(&[0xA]));
+    }
+
+    #[test]
+    fn scripted_input_holds_each_state_until_the_next() {
+        let mut source = ScriptedInput::new(vec![
+            MovieEvent { frame: 2, keypad: keys(&[1]) },
+            MovieEvent { frame: 4, keypad: keys(&[]) },
+        ]);
+
+        let polled: Vec<[bool; 16]> = (0..6).map(|frame| source.poll(frame)).collect();
+        assert_eq!(polled, vec![keys(&[]), keys(&[]), keys(&[1]), keys(&[1]), keys(&[]), keys(&[])]);
+    }
+
+    #[test]
+    fn key_scripts_are_sorted_and_commented() {
+        let path = temp_path("script", "keys");
+        fs::write(&path, "# hold 5, then 5 and A\n70 5 A\n60 5   # first\n\n80\n").unwrap();
+
+        let mut source = ScriptedInput::load_script(&path).unwrap();
+        assert_eq!(source.poll(60), keys(&[0x5]));
+        assert_eq!(source.poll(75), keys(&[0x5, 0xA]));
+        assert_eq!(source.poll(80), keys(&[]));
+
+        fs::write(&path, "60 G\n").unwrap();
+        assert!(ScriptedInput::load_script(&path).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,357 @@
+use std::{
+    fs,
+    io,
+};
+use macroquad::prelude::*;
+use crate::files::{self, invalid};
+
+// Host keys that can be named in a keymap file. Names are the KeyCode variant
+// names, matched case-insensitively (e.g. "Q", "Key1", "Up", "Kp5").
+// Backspace is missing on purpose: the window uses it to reset the game.
+const HOST_KEYS: [KeyCode; 87] = [
+    KeyCode::Space, KeyCode::Apostrophe, KeyCode::Comma, KeyCode::Minus,
+    KeyCode::Period, KeyCode::Slash, KeyCode::Semicolon, KeyCode::Equal,
+    KeyCode::Key0, KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Key5, KeyCode::Key6, KeyCode::Key7, KeyCode::Key8, KeyCode::Key9,
+    KeyCode::A, KeyCode::B, KeyCode::C, KeyCode::D, KeyCode::E, KeyCode::F,
+    KeyCode::G, KeyCode::H, KeyCode::I, KeyCode::J, KeyCode::K, KeyCode::L,
+    KeyCode::M, KeyCode::N, KeyCode::O, KeyCode::P, KeyCode::Q, KeyCode::R,
+    KeyCode::S, KeyCode::T, KeyCode::U, KeyCode::V, KeyCode::W, KeyCode::X,
+    KeyCode::Y, KeyCode::Z,
+    KeyCode::LeftBracket, KeyCode::Backslash, KeyCode::RightBracket, KeyCode::GraveAccent,
+    KeyCode::World1, KeyCode::World2,
+    KeyCode::Enter, KeyCode::Tab, KeyCode::Insert, KeyCode::Delete,
+    KeyCode::Right, KeyCode::Left, KeyCode::Down, KeyCode::Up,
+    KeyCode::PageUp, KeyCode::PageDown, KeyCode::Home, KeyCode::End,
+    KeyCode::Kp0, KeyCode::Kp1, KeyCode::Kp2, KeyCode::Kp3, KeyCode::Kp4,
+    KeyCode::Kp5, KeyCode::Kp6, KeyCode::Kp7, KeyCode::Kp8, KeyCode::Kp9,
+    KeyCode::KpDecimal, KeyCode::KpDivide, KeyCode::KpMultiply, KeyCode::KpSubtract,
+    KeyCode::KpAdd, KeyCode::KpEnter, KeyCode::KpEqual,
+    KeyCode::LeftShift, KeyCode::LeftControl, KeyCode::LeftAlt, KeyCode::LeftSuper,
+    KeyCode::RightShift, KeyCode::RightControl, KeyCode::RightAlt, KeyCode::RightSuper,
+];
+
+// Order in which the remapping screen walks the keypad, row by row:
+//
+//   1 2 3 C
+//   4 5 6 D
+//   7 8 9 E
+//   A 0 B F
+const KEYPAD_ORDER: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC,
+    0x4, 0x5, 0x6, 0xD,
+    0x7, 0x8, 0x9, 0xE,
+    0xA, 0x0, 0xB, 0xF,
+];
+
+// QWERTY keys occupying the same positions as KEYPAD_ORDER.
+const QWERTY_ORDER: [KeyCode; 16] = [
+    KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4,
+    KeyCode::Q, KeyCode::W, KeyCode::E, KeyCode::R,
+    KeyCode::A, KeyCode::S, KeyCode::D, KeyCode::F,
+    KeyCode::Z, KeyCode::X, KeyCode::C, KeyCode::V,
+];
+
+pub fn key_name(keycode: KeyCode) -> String {
+    format!("{:?}", keycode)
+}
+
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+    HOST_KEYS
+        .iter()
+        .copied()
+        .find(|keycode| key_name(*keycode).eq_ignore_ascii_case(name))
+}
+
+// Maps each CHIP-8 key to one or more host keys.
+//
+// Keymap files are plain text. Bindings under [default] apply to every ROM,
+// and a section named after a ROM's SHA-1 overrides individual keys for that
+// ROM only:
+//
+//   [default]
+//   1 = Key1
+//   5 = W Up
+//
+//   [2f1d6ad0b5e4a1...]
+//   5 = Space
+//
+// Extra keys from the ROM database are kept apart and never saved.
+#[derive(Clone)]
+pub struct Keymap {
+    bindings: [Vec<KeyCode>; 16],
+    // What [default] binds, so saving only writes the keys this ROM changes
+    defaults: [Vec<KeyCode>; 16],
+    extras: [Vec<KeyCode>; 16],
+    path: Option<String>,
+    rom_sha1: String,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut bindings: [Vec<KeyCode>; 16] = Default::default();
+        for (key, keycode) in KEYPAD_ORDER.iter().zip(QWERTY_ORDER) {
+            bindings[*key as usize] = vec![keycode];
+        }
+
+        Self {
+            defaults: bindings.clone(),
+            bindings,
+            extras: Default::default(),
+            path: None,
+            rom_sha1: String::new(),
+        }
+    }
+}
+
+impl Keymap {
+    // Loads the default bindings and any override for the given ROM. A missing
+    // file leaves the QWERTY layout in place.
+    pub fn load(path: &str, rom_sha1: &str) -> io::Result<Self> {
+        let mut keymap = Self {
+            path: Some(path.to_string()),
+            rom_sha1: rom_sha1.to_string(),
+            ..Self::default()
+        };
+
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(keymap),
+            Err(e) => return Err(e),
+        };
+
+        // The ROM's section wins wherever it appears in the file
+        let mut overrides: [Option<Vec<KeyCode>>; 16] = Default::default();
+        let mut section = String::from("default");
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[').and_then(|rest| rest.strip_suffix(']')) {
+                section = name.trim().to_lowercase();
+                continue;
+            }
+
+            if section != "default" && section != keymap.rom_sha1 {
+                continue;
+            }
+
+            let (key, host_keys) = match line.split_once('=') {
+                Some(pair) => pair,
+                None => return Err(invalid(format!("{}: bad binding: {}", path, line))),
+            };
+
+            let key = match u8::from_str_radix(key.trim(), 16) {
+                Ok(key) if key < 16 => key,
+                _ => return Err(invalid(format!("{}: bad CHIP-8 key: {}", path, key.trim()))),
+            };
+
+            let mut keycodes = Vec::new();
+            for name in host_keys.split_whitespace() {
+                match key_from_name(name) {
+                    Some(keycode) => keycodes.push(keycode),
+                    None => return Err(invalid(format!("{}: unknown host key: {}", path, name))),
+                }
+            }
+
+            if section == "default" {
+                keymap.defaults[key as usize] = keycodes;
+            } else {
+                overrides[key as usize] = Some(keycodes);
+            }
+        }
+
+        for (key, keycodes) in overrides.into_iter().enumerate() {
+            keymap.bindings[key] = keycodes.unwrap_or_else(|| keymap.defaults[key].clone());
+        }
+
+        Ok(keymap)
+    }
+
+    // Writes the bindings that differ from [default] as the override section
+    // for this ROM, replacing any previous override and leaving other
+    // sections untouched.
+    pub fn save(&self) -> io::Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let mut body = String::new();
+        for key in KEYPAD_ORDER {
+            if self.bindings[key as usize] != self.defaults[key as usize] {
+                let names: Vec<String> = self.bindings[key as usize].iter().map(|keycode| key_name(*keycode)).collect();
+                body.push_str(&format!("{:X} = {}\n", key, names.join(" ")));
+            }
+        }
+        files::save_section(path, &self.rom_sha1, &body)
+    }
+
+    // The bound keys followed by any extras no binding has taken since
+    pub fn keys_for(&self, key: u8) -> Vec<KeyCode> {
+        let mut keycodes = self.bindings[key as usize].clone();
+        for keycode in self.extras[key as usize].iter() {
+            if !self.bindings.iter().any(|keycodes| keycodes.contains(keycode)) {
+                keycodes.push(*keycode);
+            }
+        }
+        keycodes
+    }
+
+    pub fn bind(&mut self, key: u8, keycodes: Vec<KeyCode>) {
+        self.bindings[key as usize] = keycodes;
+    }
+
+    // Adds a host key to a CHIP-8 key unless that host key is already in use.
+    // Extras come from the ROM database and aren't saved.
+    pub fn bind_extra(&mut self, key: u8, keycode: KeyCode) {
+        if self.bindings.iter().chain(self.extras.iter()).any(|keycodes| keycodes.contains(&keycode)) {
+            return;
+        }
+        self.extras[key as usize].push(keycode);
+    }
+}
+
+// Walks through the keypad asking for a host key for each CHIP-8 key. Enter
+// keeps the current binding and Escape abandons the remap. Returns whether the
+// keymap was changed.
+pub async fn remap_screen(keymap: &mut Keymap) -> bool {
+    let mut remapped = keymap.clone();
+    let mut position = 0;
+
+    // Don't let the key that opened this screen count as the first answer
+    next_frame().await;
+
+    while position < KEYPAD_ORDER.len() {
+        let key = KEYPAD_ORDER[position];
+
+        match get_last_key_pressed() {
+            Some(KeyCode::Escape) => return false,
+            Some(KeyCode::Enter) => position += 1,
+            Some(keycode) if HOST_KEYS.contains(&keycode) => {
+                remapped.bind(key, vec![keycode]);
+                position += 1;
+            }
+            _ => {}
+        }
+
+        clear_background(BLACK);
+
+        let current: Vec<String> = keymap.keys_for(key).iter().map(|keycode| key_name(*keycode)).collect();
+        draw_text(format!("Press a key for CHIP-8 key {:X}", key), 20.0, 40.0, 32.0, WHITE);
+        draw_text(format!("Current: {}", current.join(" ")), 20.0, 80.0, 24.0, GRAY);
+        draw_text("Enter: keep current    Escape: cancel", 20.0, 120.0, 24.0, GRAY);
+
+        next_frame().await;
+    }
+
+    *keymap = remapped;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::temp_path;
+
+    #[test]
+    fn the_default_layout_is_qwerty() {
+        let keymap = Keymap::default();
+        assert_eq!(keymap.keys_for(0x1), &[KeyCode::Key1]);
+        assert_eq!(keymap.keys_for(0xC), &[KeyCode::Key4]);
+        assert_eq!(keymap.keys_for(0x0), &[KeyCode::X]);
+        assert_eq!(keymap.keys_for(0xF), &[KeyCode::V]);
+    }
+
+    #[test]
+    fn key_names_round_trip() {
+        for keycode in HOST_KEYS {
+            assert!(key_from_name(&key_name(keycode)) == Some(keycode));
+        }
+        assert!(key_from_name("kp5") == Some(KeyCode::Kp5));
+        assert!(key_from_name("Nope").is_none());
+    }
+
+    #[test]
+    fn rom_sections_override_the_defaults() {
+        let path = temp_path("keymap", "cfg");
+        fs::write(&path, "# comment\n[default]\n5 = W Up\n\n[ABCD]\n5 = Space\n\n[ffff]\n6 = Down\n").unwrap();
+
+        let keymap = Keymap::load(&path, "abcd").unwrap();
+        assert_eq!(keymap.keys_for(0x5), &[KeyCode::Space]);
+        assert_eq!(keymap.keys_for(0x6), &[KeyCode::E]);
+
+        let other = Keymap::load(&path, "1234").unwrap();
+        assert_eq!(other.keys_for(0x5), &[KeyCode::W, KeyCode::Up]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bad_bindings_are_refused() {
+        let path = temp_path("badkeymap", "cfg");
+        for text in ["5 W\n", "G = W\n", "5 = Nope\n"] {
+            fs::write(&path, text).unwrap();
+            assert!(Keymap::load(&path, "abcd").is_err(), "loaded {:?}", text);
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn saving_replaces_only_this_roms_section() {
+        let path = temp_path("savekeymap", "cfg");
+        fs::write(&path, "[default]\n5 = W\n\n[abcd]\n5 = Space\n").unwrap();
+
+        let mut keymap = Keymap::load(&path, "abcd").unwrap();
+        keymap.bind(0x5, vec![KeyCode::Enter]);
+        keymap.save().unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("[default]\n5 = W\n"));
+        assert!(!text.contains("Space"));
+        assert_eq!(Keymap::load(&path, "abcd").unwrap().keys_for(0x5), &[KeyCode::Enter]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn saving_writes_only_changed_keys() {
+        let path = temp_path("changedkeymap", "cfg");
+        fs::write(&path, "[default]\n5 = W\n").unwrap();
+
+        let mut keymap = Keymap::load(&path, "abcd").unwrap();
+        keymap.bind_extra(0x8, KeyCode::Down);
+        keymap.bind(0x6, vec![KeyCode::Space]);
+        keymap.save().unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(text, "[default]\n5 = W\n\n[abcd]\n6 = Space\n");
+
+        // Binding it back to the default leaves nothing to override
+        keymap.bind(0x6, vec![KeyCode::E]);
+        keymap.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "[default]\n5 = W\n");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn extra_keys_never_steal_a_bound_key() {
+        let mut keymap = Keymap::default();
+        keymap.bind_extra(0x5, KeyCode::Up);
+        keymap.bind_extra(0x6, KeyCode::Up);
+        keymap.bind_extra(0x8, KeyCode::W);
+
+        assert_eq!(keymap.keys_for(0x5), &[KeyCode::W, KeyCode::Up]);
+        assert_eq!(keymap.keys_for(0x6), &[KeyCode::E]);
+        assert_eq!(keymap.keys_for(0x8), &[KeyCode::S]);
+
+        // Nor keep one a remap takes
+        keymap.bind(0x7, vec![KeyCode::Up]);
+        assert_eq!(keymap.keys_for(0x5), &[KeyCode::W]);
+    }
+}
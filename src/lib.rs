@@ -0,0 +1,30 @@
+// The emulator core and its frontends. The binary in main.rs only parses the
+// command line and picks a frontend.
+pub mod analysis;
+pub mod bench;
+pub mod browser;
+pub mod capture;
+pub mod cheats;
+pub mod chip8;
+pub mod editor;
+pub mod files;
+pub mod framebuffer;
+pub mod headless;
+pub mod heatmap;
+pub mod input;
+pub mod keymap;
+pub mod movie;
+pub mod palette;
+pub mod patch;
+pub mod persistence;
+pub mod profile;
+pub mod quirks;
+pub mod recording;
+pub mod render;
+pub mod romdb;
+pub mod romfile;
+pub mod savestate;
+pub mod symbols;
+pub mod tui;
+pub mod watch;
+pub mod window;
@@ -1,18 +1,364 @@
-mod chip8;
-
 use std::{
+    collections::HashMap,
+    env,
     fs,
     io,
     io::Read,
+    path::Path,
+    process,
     thread,
     time
 };
-use macroquad::main;
-use crate::chip8::Machine;
+use macroquad::Window;
+use rustchip8::{analysis, bench, browser, cheats, headless, palette, romdb, romfile, tui, window};
+use rustchip8::cheats::CheatList;
+use rustchip8::chip8::{Machine, MAX_ROM_SIZE};
+use rustchip8::headless::HeadlessOptions;
+use rustchip8::input::{InputSource, KeyboardInput, ManualInput, ScriptedInput};
+use rustchip8::keymap::Keymap;
+use rustchip8::palette::Palette;
+use rustchip8::persistence::PersistenceMode;
+use rustchip8::render::ScaleMode;
+use rustchip8::savestate::SaveState;
+use rustchip8::symbols::SymbolTable;
+use rustchip8::tui::TerminalInput;
+use rustchip8::window::WindowOptions;
+
+// Run by the terminal and headless frontends and cfg when no ROM is named;
+// only the window has a browser to pick one from
+const DEFAULT_ROM: &str = "roms/keypad.ch8";
+
+// Command-line options shared by every frontend
+#[derive(Clone)]
+struct Options {
+    // None opens the ROM browser in the window frontend and runs DEFAULT_ROM
+    // in the others
+    rom: Option<String>,
+    rom_dir: String,
+    record: Option<String>,
+    play: Option<String>,
+    keymap_path: String,
+    screenshot_scale: Option<usize>,
+    palette: Option<Palette>,
+    persistence: Option<PersistenceMode>,
+    scale_mode: ScaleMode,
+    fullscreen: bool,
+    record_gif: Option<String>,
+    record_av: Option<String>,
+    frames: Option<u64>,
+    gif_decimate: u64,
+    detect_loops: bool,
+    profile: Option<String>,
+    symbols: Option<String>,
+    patch: Option<String>,
+    watch: bool,
+    watch_keep_keys: bool,
+    reload_state: Option<String>,
+    // Off for headless runs, which should only depend on their arguments
+    saved_cheats: bool,
+}
+
+impl Options {
+    fn parse(args: Vec<String>) -> Self {
+        let mut options = Self {
+            rom: None,
+            rom_dir: String::from("roms"),
+            record: None,
+            play: None,
+            keymap_path: String::from("keymap.cfg"),
+            screenshot_scale: None,
+            palette: None,
+            persistence: None,
+            scale_mode: ScaleMode::Fit,
+            fullscreen: false,
+            record_gif: None,
+            record_av: None,
+            frames: None,
+            gif_decimate: 1,
+            detect_loops: false,
+            profile: None,
+            symbols: None,
+            patch: None,
+            watch: false,
+            watch_keep_keys: false,
+            reload_state: None,
+            saved_cheats: true,
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--record" => options.record = args.next(),
+                "--play" => options.play = args.next(),
+                "--keymap" => options.keymap_path = args.next().unwrap_or(options.keymap_path),
+                "--palette" => options.palette = args.next().map(|spec| match Palette::parse(&spec) {
+                    Some(palette) => palette,
+                    None => panic!("Unknown palette: {}", spec),
+                }),
+                "--persistence" => options.persistence = args.next().map(|mode| match PersistenceMode::parse(&mode) {
+                    Some(mode) => mode,
+                    None => panic!("Unknown persistence mode: {}", mode),
+                }),
+                "--scale" => options.scale_mode = args.next().map(|mode| match ScaleMode::parse(&mode) {
+                    Some(mode) => mode,
+                    None => panic!("Unknown scale mode: {}", mode),
+                }).unwrap_or(options.scale_mode),
+                "--fullscreen" => options.fullscreen = true,
+                "--record-gif" => options.record_gif = args.next(),
+                "--record-av" => options.record_av = args.next(),
+                "--frames" => options.frames = args.next().and_then(|frames| frames.parse().ok()),
+                "--decimate" => options.gif_decimate = args.next().and_then(|decimate| decimate.parse().ok()).unwrap_or(options.gif_decimate),
+                "--detect-loops" => options.detect_loops = true,
+                "--profile" => options.profile = args.next(),
+                "--symbols" => options.symbols = args.next(),
+                "--patch" => options.patch = args.next(),
+                "--watch" => options.watch = true,
+                "--watch-keep-keys" => options.watch_keep_keys = true,
+                "--reload-state" => options.reload_state = args.next(),
+                "--rom-dir" => options.rom_dir = args.next().unwrap_or(options.rom_dir),
+                "--screenshot-scale" => options.screenshot_scale = args.next().and_then(|scale| scale.parse().ok()),
+                _ => options.rom = Some(arg),
+            }
+        }
+
+        options
+    }
+
+    // The options for a ROM chosen in the browser after this one: display and
+    // input settings carry over, while recordings, patches, symbols and the
+    // like belonged to the ROM named on the command line
+    fn for_next_rom(&self) -> Self {
+        Self {
+            rom: None,
+            record: None,
+            play: None,
+            record_gif: None,
+            record_av: None,
+            frames: None,
+            profile: None,
+            symbols: None,
+            patch: None,
+            reload_state: None,
+            ..self.clone()
+        }
+    }
+}
+
+// Loads the ROM and applies the ROM database, saved settings and command-line
+// options. `make_input` builds the frontend's input source from the keymap.
+fn setup(options: Options, make_input: impl FnOnce(Keymap) -> Box<dyn InputSource>) -> Machine {
+    let rom: String = options.rom.clone().unwrap_or_else(|| String::from(DEFAULT_ROM));
 
-#[main("Chip8")]
-async fn main() {
     let mut m: Machine = Machine::new();
-    m.init(String::from("roms/keypad.ch8"));
-    m.run().await;
+    if let Some(ref path) = options.patch {
+        m.set_patch(path);
+    }
+    m.init(rom.clone());
+
+    if let Some(symbols) = load_symbols(&rom, options.symbols.as_deref()) {
+        m.set_symbols(symbols);
+    }
+
+    let mut keymap = match Keymap::load(&options.keymap_path, &m.rom_sha1()) {
+        Ok(keymap) => keymap,
+        Err(why) => panic!("{}", why),
+    };
+
+    // ROMs the database doesn't know get their platform from the extension
+    let settings = match romdb::lookup(&m.rom_sha1(), romdb::USER_DATABASE) {
+        Ok(settings) => settings,
+        Err(why) => panic!("{}", why),
+    };
+    let settings = settings.or_else(|| romfile::rom_name(&rom).ok().and_then(|name| romdb::guess_from_extension(&name)));
+    if let Some(settings) = settings {
+        println!("{} ({})", settings.title, settings.platform);
+        m.set_quirks(settings.quirks);
+        m.set_tick_rate(settings.tick_rate);
+        if let Some(palette) = settings.palette {
+            m.set_palette(palette);
+        }
+        for (key, keycode) in settings.keys {
+            keymap.bind_extra(key, keycode);
+        }
+    }
+    m.set_input(make_input(keymap));
+
+    // A palette chosen on the command line beats one picked in-app, which
+    // beats the database's colours
+    let saved_palette = match Palette::load_saved(palette::SAVED_PALETTES, &m.rom_sha1()) {
+        Ok(saved_palette) => saved_palette,
+        Err(why) => panic!("{}", why),
+    };
+    if let Some(palette) = options.palette.or(saved_palette) {
+        m.set_palette(palette);
+    }
+
+    if options.saved_cheats {
+        match CheatList::load(cheats::SAVED_CHEATS, &m.rom_sha1()) {
+            Ok(cheats) => m.set_cheats(cheats),
+            Err(why) => panic!("{}", why),
+        }
+    }
+
+    m.set_loop_detection(options.detect_loops);
+
+    if let Some(scale) = options.screenshot_scale {
+        m.set_screenshot_scale(scale);
+    }
+
+    if options.watch {
+        m.watch_rom(options.watch_keep_keys);
+    }
+    if let Some(path) = options.reload_state {
+        match SaveState::load(&path) {
+            Ok(state) => m.set_reload_state(Some(state)),
+            Err(why) => panic!("{}", why),
+        }
+    }
+
+    if let Some(filename) = options.profile {
+        m.start_profiling(&filename);
+    }
+    if let Some(filename) = options.play {
+        m.play_movie(&filename);
+    }
+    if let Some(filename) = options.record {
+        m.record_movie(&filename);
+    }
+    if let Some(filename) = options.record_gif {
+        m.start_gif_recording(&filename, options.gif_decimate, options.frames);
+    }
+    if let Some(base) = options.record_av {
+        m.start_av_recording(&base, options.frames);
+    }
+
+    m
+}
+
+// Symbols given on the command line are copied next to the ROM so they load
+// by themselves next time
+fn load_symbols(rom: &str, path: Option<&str>) -> Option<SymbolTable> {
+    let result = match path {
+        Some(path) => SymbolTable::load(path).and_then(|symbols| {
+            symbols.save(&SymbolTable::path_for_rom(rom))?;
+            Ok(Some(symbols))
+        }),
+        None => SymbolTable::load_for_rom(rom),
+    };
+
+    match result {
+        Ok(symbols) => symbols,
+        Err(why) => panic!("{}", why),
+    }
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        Some("bench") => bench::run(),
+        Some("tui") => {
+            args.remove(0);
+            let keys = tui::TerminalKeys::default();
+            let input_keys = keys.clone();
+            let mut m = setup(Options::parse(args), |keymap| Box::new(TerminalInput::new(keymap, input_keys)));
+            if let Err(why) = tui::run(&mut m, keys).and_then(|_| m.write_profile()) {
+                panic!("{}", why);
+            }
+        }
+        Some("cfg") => {
+            let rom = args.get(1).cloned().unwrap_or_else(|| String::from(DEFAULT_ROM));
+            let bytes = match romfile::read(&rom) {
+                Ok(bytes) => bytes,
+                Err(why) => panic!("{}", why),
+            };
+            if bytes.len() > MAX_ROM_SIZE {
+                panic!("{} is {} bytes, too large to fit in memory", rom, bytes.len());
+            }
+
+            let symbols = load_symbols(&rom, None).unwrap_or_default();
+            let graph = analysis::analyze(&bytes, &symbols);
+            for address in graph.computed_jumps.iter() {
+                eprintln!("computed jump at {}", symbols.name(*address));
+            }
+            for (address, target) in graph.code_writes.iter() {
+                eprintln!("write into code at {} from {}", symbols.name(*target), symbols.name(*address));
+            }
+
+            let dot = graph.to_dot(&bytes, &symbols);
+            let result = match args.get(2) {
+                Some(path) => fs::write(path, dot),
+                None => io::Write::write_all(&mut io::stdout(), dot.as_bytes()),
+            };
+            if let Err(why) = result {
+                panic!("{}", why);
+            }
+        }
+        Some("run-headless") => {
+            args.remove(0);
+            let (headless_options, args) = HeadlessOptions::parse(args);
+            let keys = headless_options.keys.as_ref().map(|path| match ScriptedInput::load_script(path) {
+                Ok(keys) => keys,
+                Err(why) => panic!("{}", why),
+            });
+            let options = Options {
+                saved_cheats: false,
+                ..Options::parse(args)
+            };
+            let mut m = setup(options, |_| match keys {
+                Some(keys) => Box::new(keys),
+                None => Box::new(ManualInput::new()),
+            });
+            match headless::run(&mut m, &headless_options) {
+                Ok(code) => process::exit(code),
+                Err(why) => panic!("{}", why),
+            }
+        }
+        _ => Window::new("Chip8", run_window(Options::parse(args))),
+    }
+}
+
+// Without a ROM, or given a directory, the player picks one in the browser.
+// Leaving a game with Escape, or a crash, goes back to the browser, and
+// leaving the browser quits.
+async fn run_window(mut options: Options) {
+    let mut dir: String = options.rom_dir.clone();
+    let mut last_rom: Option<String> = None;
+    // Why each ROM that crashed did, so the browser won't start it again
+    let mut crashes: HashMap<String, String> = HashMap::new();
+
+    loop {
+        let rom = match options.rom.take() {
+            Some(rom) if !Path::new(&rom).is_dir() => rom,
+            named => {
+                if let Some(named) = named {
+                    dir = named;
+                }
+                match browser::pick_rom(&dir, last_rom.as_deref(), &crashes).await {
+                    Some(rom) => rom,
+                    None => return,
+                }
+            }
+        };
+
+        let next_options = options.for_next_rom();
+        options.rom = Some(rom.clone());
+        let window_options = WindowOptions {
+            persistence: options.persistence,
+            scale_mode: options.scale_mode,
+            fullscreen: options.fullscreen,
+        };
+
+        let mut m = setup(options, |keymap| Box::new(KeyboardInput::new(keymap)));
+        window::run(&mut m, window_options).await;
+        if let Some(crash) = m.crash() {
+            crashes.insert(rom.clone(), crash.to_string());
+        }
+        if let Err(why) = m.write_profile() {
+            panic!("{}", why);
+        }
+
+        options = next_options;
+        last_rom = Some(rom);
+    }
 }
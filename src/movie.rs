@@ -0,0 +1,317 @@
+use std::{
+    fs,
+    io,
+    io::BufRead,
+    io::Write,
+};
+use crate::cheats::Cheat;
+use crate::files::invalid;
+use crate::quirks::Quirks;
+
+const MAGIC: &str = "rustchip8-movie 1";
+
+// A movie is a header describing the session followed by every keypad state
+// change, stamped with the frame it took effect on. Keypad states are stored
+// as a 16-bit mask where bit N is CHIP-8 key N. The cheats line is only
+// written when cheats were on, and replaces the player's own cheats on replay.
+//
+//   rustchip8-movie 1
+//   rom_sha1 2f1d6ad0b5e4a1...
+//   seed 1697558400
+//   tick_rate 15
+//   quirks shift=1 memoryIncrementByX=0 memoryLeaveIUnchanged=1 wrap=1 jump=0 vblank=0 logic=0
+//   cheats v3=09 0x2f0=03
+//   ---
+//   0 0000
+//   132 0020
+//   140 0000
+pub struct MovieHeader {
+    pub rom_sha1: String,
+    pub seed: u64,
+    // Instructions per frame. None for movies recorded before it was stored,
+    // which replay at whatever rate the ROM is set up with.
+    pub tick_rate: Option<u32>,
+    pub quirks: Quirks,
+    pub cheats: Vec<Cheat>,
+}
+
+pub struct MovieEvent {
+    pub frame: u64,
+    pub keypad: [bool; 16],
+}
+
+pub struct Movie {
+    pub header: MovieHeader,
+    pub events: Vec<MovieEvent>,
+}
+
+fn keypad_to_mask(keypad: &[bool; 16]) -> u16 {
+    let mut mask: u16 = 0;
+    for (key, pressed) in keypad.iter().enumerate() {
+        if *pressed {
+            mask |= 1 << key;
+        }
+    }
+    mask
+}
+
+fn keypad_from_mask(mask: u16) -> [bool; 16] {
+    let mut keypad = [false; 16];
+    for (key, pressed) in keypad.iter_mut().enumerate() {
+        *pressed = mask & (1 << key) != 0;
+    }
+    keypad
+}
+
+impl Movie {
+    pub fn load(filename: &str) -> io::Result<Self> {
+        let file = fs::File::open(filename)?;
+        let mut lines = io::BufReader::new(file).lines();
+
+        let magic = lines.next().transpose()?;
+        if magic.as_deref() != Some(MAGIC) {
+            return Err(invalid(format!("{} is not a movie file", filename)));
+        }
+
+        let mut rom_sha1 = String::new();
+        let mut seed: u64 = 0;
+        let mut tick_rate: Option<u32> = None;
+        let mut quirks = Quirks::default();
+        let mut cheats: Vec<Cheat> = Vec::new();
+
+        for line in lines.by_ref() {
+            let line = line?;
+            if line == "---" {
+                break;
+            }
+
+            match line.split_once(' ') {
+                Some(("rom_sha1", value)) => rom_sha1 = value.to_string(),
+                Some(("seed", value)) => {
+                    seed = value.parse().map_err(|_| invalid(format!("bad seed: {}", value)))?;
+                }
+                Some(("tick_rate", value)) => {
+                    tick_rate = Some(value.parse().map_err(|_| invalid(format!("bad tick rate: {}", value)))?);
+                }
+                // Written by recorders that predate configurable quirks
+                Some(("quirks", "none")) => {}
+                Some(("quirks", value)) => {
+                    quirks = Quirks::parse(value).ok_or_else(|| invalid(format!("bad quirks: {}", value)))?;
+                }
+                Some(("cheats", value)) => {
+                    for cheat in value.split_whitespace() {
+                        cheats.push(Cheat::parse(cheat).ok_or_else(|| invalid(format!("bad cheat: {}", cheat)))?);
+                    }
+                }
+                // Unknown header fields are ignored so newer movies stay readable
+                _ => {}
+            }
+        }
+
+        let mut events = Vec::new();
+
+        for line in lines {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+
+            let (frame, mask) = match line.split_once(' ') {
+                Some(pair) => pair,
+                None => return Err(invalid(format!("bad movie event: {}", line))),
+            };
+            let frame: u64 = frame.parse().map_err(|_| invalid(format!("bad frame: {}", frame)))?;
+            let mask: u16 = u16::from_str_radix(mask, 16).map_err(|_| invalid(format!("bad keypad mask: {}", mask)))?;
+
+            events.push(MovieEvent { frame, keypad: keypad_from_mask(mask) });
+        }
+
+        Ok(Self {
+            header: MovieHeader { rom_sha1, seed, tick_rate, quirks, cheats },
+            events,
+        })
+    }
+}
+
+// Writes events to disk as they happen, so a session that ends by closing the
+// window still leaves a complete movie behind.
+pub struct MovieRecorder {
+    writer: io::BufWriter<fs::File>,
+    last_keypad: Option<[bool; 16]>,
+}
+
+impl MovieRecorder {
+    pub fn create(filename: &str, header: &MovieHeader) -> io::Result<Self> {
+        let mut writer = io::BufWriter::new(fs::File::create(filename)?);
+
+        writeln!(writer, "{}", MAGIC)?;
+        writeln!(writer, "rom_sha1 {}", header.rom_sha1)?;
+        writeln!(writer, "seed {}", header.seed)?;
+        if let Some(tick_rate) = header.tick_rate {
+            writeln!(writer, "tick_rate {}", tick_rate)?;
+        }
+        writeln!(writer, "quirks {}", header.quirks)?;
+        if !header.cheats.is_empty() {
+            let cheats: Vec<String> = header.cheats.iter().map(|cheat| cheat.to_string()).collect();
+            writeln!(writer, "cheats {}", cheats.join(" "))?;
+        }
+        writeln!(writer, "---")?;
+        writer.flush()?;
+
+        Ok(Self {
+            writer,
+            last_keypad: None,
+        })
+    }
+
+    pub fn record(&mut self, frame: u64, keypad: &[bool; 16]) -> io::Result<()> {
+        if self.last_keypad.as_ref() == Some(keypad) {
+            return Ok(());
+        }
+
+        writeln!(self.writer, "{} {:04x}", frame, keypad_to_mask(keypad))?;
+        self.writer.flush()?;
+        self.last_keypad = Some(*keypad);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::temp_path;
+    use crate::cheats::CheatTarget;
+    use crate::chip8::{Config, Machine};
+    use crate::input::ManualInput;
+
+    #[test]
+    fn masks_put_key_n_in_bit_n() {
+        let mut keypad = [false; 16];
+        keypad[0x5] = true;
+        keypad[0xF] = true;
+
+        assert_eq!(keypad_to_mask(&keypad), 0x8020);
+        assert_eq!(keypad_from_mask(0x8020), keypad);
+    }
+
+    #[test]
+    fn loads_the_header_and_events() {
+        let path = temp_path("load", "movie");
+        fs::write(&path, "rustchip8-movie 1\nrom_sha1 abc123\nseed 1697558400\ntick_rate 15\nquirks shift=1 wrap=0\ncheats v3=09 0x2f0=03\nnewer field\n---\n0 0000\n132 0020\n\n140 0000\n").unwrap();
+
+        let movie = Movie::load(&path).unwrap();
+        assert_eq!(movie.header.rom_sha1, "abc123");
+        assert_eq!(movie.header.seed, 1697558400);
+        assert_eq!(movie.header.tick_rate, Some(15));
+        assert!(movie.header.quirks.shift);
+        assert!(!movie.header.quirks.wrap);
+        assert_eq!(movie.header.cheats.len(), 2);
+        assert!(movie.header.cheats[1].target == CheatTarget::Memory(0x2F0));
+
+        let frames: Vec<u64> = movie.events.iter().map(|event| event.frame).collect();
+        assert_eq!(frames, vec![0, 132, 140]);
+        assert!(movie.events[1].keypad[5]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn old_movies_without_quirks_load() {
+        let path = temp_path("old", "movie");
+        fs::write(&path, "rustchip8-movie 1\nrom_sha1 abc123\nseed 1\nquirks none\n---\n").unwrap();
+
+        let movie = Movie::load(&path).unwrap();
+        assert!(movie.header.quirks == Quirks::default());
+        assert_eq!(movie.header.tick_rate, None);
+        assert!(movie.events.is_empty());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bad_movies_are_refused() {
+        let path = temp_path("bad", "movie");
+        for text in [
+            "not a movie\n---\n",
+            "rustchip8-movie 1\nseed soon\n---\n",
+            "rustchip8-movie 1\ntick_rate fast\n---\n",
+            "rustchip8-movie 1\nquirks shift=2\n---\n",
+            "rustchip8-movie 1\ncheats v3\n---\n",
+            "rustchip8-movie 1\n---\n10\n",
+            "rustchip8-movie 1\n---\n10 zzzz\n",
+        ] {
+            fs::write(&path, text).unwrap();
+            assert!(Movie::load(&path).is_err(), "loaded {:?}", text);
+        }
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn recording_skips_unchanged_keypads() {
+        let path = temp_path("record", "movie");
+        let header = MovieHeader {
+            rom_sha1: String::from("abc123"),
+            seed: 7,
+            tick_rate: Some(20),
+            quirks: Quirks::default(),
+            cheats: Vec::new(),
+        };
+
+        let mut keypad = [false; 16];
+        let mut recorder = MovieRecorder::create(&path, &header).unwrap();
+        recorder.record(0, &keypad).unwrap();
+        recorder.record(1, &keypad).unwrap();
+        keypad[0xA] = true;
+        recorder.record(2, &keypad).unwrap();
+        drop(recorder);
+
+        let movie = Movie::load(&path).unwrap();
+        assert_eq!(movie.header.seed, 7);
+        assert_eq!(movie.header.tick_rate, Some(20));
+        assert_eq!(movie.events.iter().map(|event| event.frame).collect::<Vec<u64>>(), vec![0, 2]);
+        assert!(!fs::read_to_string(&path).unwrap().contains("cheats"));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replaying_a_recording_reproduces_the_run() {
+        // Wait for a key into V0, add a random number to it, and go again
+        let rom = [0xF0, 0x0A, 0xC1, 0xFF, 0x80, 0x14, 0x12, 0x00];
+        let path = temp_path("replay", "movie");
+
+        let keys = ManualInput::new();
+        let mut recording = Machine::from_rom_bytes(&rom, Config { seed: Some(1234), ..Config::default() }).unwrap();
+        recording.set_input(Box::new(keys.clone()));
+        recording.record_movie(&path);
+        for frame in 0..40 {
+            match frame {
+                3 => keys.press(0x5),
+                6 => keys.release(0x5),
+                20 => keys.press(0xA),
+                24 => keys.release(0xA),
+                _ => {}
+            }
+            recording.step_frame();
+        }
+
+        // A different seed and tick rate, which the movie must put back
+        let config = Config {
+            seed: Some(1),
+            tick_rate: 7,
+            ..Config::default()
+        };
+        let mut replay = Machine::from_rom_bytes(&rom, config).unwrap();
+        replay.play_movie(&path);
+        for _ in 0..40 {
+            replay.step_frame();
+        }
+
+        assert_ne!(recording.registers()[0], 0);
+        assert_eq!(replay.state_report(), recording.state_report());
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use std::{
+    fs,
+    io,
+};
+use macroquad::prelude::*;
+use crate::files;
+
+// Chosen palettes are remembered per ROM in this file, one "<sha1> = <spec>"
+// line per ROM.
+pub const SAVED_PALETTES: &str = "palettes.cfg";
+
+// Colours for background, plane 1, plane 2 and pixels lit in both planes.
+// Single-plane programs only use the first two.
+const BUILTIN: [(&str, [u32; 4]); 4] = [
+    ("classic", [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+    ("amber", [0x1A0F00, 0xFFB000, 0xB37A00, 0xFFD280]),
+    ("green", [0x001200, 0x33FF33, 0x1F9F1F, 0x99FF99]),
+    ("lcd", [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F]),
+];
+
+// Accepts "#RRGGBB" and "#RGB"
+pub fn parse_hex_color(text: &str) -> Option<Color> {
+    let hex = text.trim().strip_prefix('#').unwrap_or(text.trim());
+    let value = u32::from_str_radix(hex, 16).ok()?;
+
+    let (r, g, b) = match hex.len() {
+        6 => ((value >> 16) & 0xFF, (value >> 8) & 0xFF, value & 0xFF),
+        3 => (((value >> 8) & 0xF) * 0x11, ((value >> 4) & 0xF) * 0x11, (value & 0xF) * 0x11),
+        _ => return None,
+    };
+
+    Some(Color::from_rgba(r as u8, g as u8, b as u8, 255))
+}
+
+fn hex_color(color: Color) -> String {
+    format!(
+        "#{:02x}{:02x}{:02x}",
+        (color.r * 255.0).round() as u8,
+        (color.g * 255.0).round() as u8,
+        (color.b * 255.0).round() as u8,
+    )
+}
+
+#[derive(Clone, PartialEq)]
+pub struct Palette {
+    // None for user-defined palettes
+    pub name: Option<String>,
+    pub colors: [Color; 4],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::builtin(0)
+    }
+}
+
+impl Palette {
+    fn builtin(index: usize) -> Self {
+        let (name, colors) = BUILTIN[index % BUILTIN.len()];
+
+        Self {
+            name: Some(name.to_string()),
+            colors: colors.map(Color::from_hex),
+        }
+    }
+
+    // Builds a palette from two or four colours. With two, both planes use the
+    // foreground colour.
+    pub fn from_colors(colors: &[Color]) -> Option<Self> {
+        let colors = match *colors {
+            [background, foreground] => [background, foreground, foreground, foreground],
+            [background, plane1, plane2, both] => [background, plane1, plane2, both],
+            _ => return None,
+        };
+
+        Some(Self { name: None, colors })
+    }
+
+    // Parses either a built-in palette name or a comma-separated list of two or
+    // four hex colours, e.g. "amber" or "#000,#0f0".
+    pub fn parse(spec: &str) -> Option<Self> {
+        let spec = spec.trim();
+
+        if let Some(index) = BUILTIN.iter().position(|(name, _)| name.eq_ignore_ascii_case(spec)) {
+            return Some(Self::builtin(index));
+        }
+
+        let colors: Option<Vec<Color>> = spec.split(',').map(parse_hex_color).collect();
+        Self::from_colors(&colors?)
+    }
+
+    pub fn spec(&self) -> String {
+        match self.name {
+            Some(ref name) => name.clone(),
+            None => self.colors.iter().map(|color| hex_color(*color)).collect::<Vec<String>>().join(","),
+        }
+    }
+
+    // The built-in palette after this one; user-defined palettes cycle back to
+    // the first.
+    pub fn next(&self) -> Self {
+        let index = BUILTIN
+            .iter()
+            .position(|(name, _)| Some(name.to_string()) == self.name)
+            .map(|index| index + 1)
+            .unwrap_or(0);
+
+        Self::builtin(index)
+    }
+
+    pub fn background(&self) -> Color {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> Color {
+        self.colors[1]
+    }
+
+    pub fn load_saved(path: &str, rom_sha1: &str) -> io::Result<Option<Self>> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        for line in text.lines() {
+            if let Some((sha1, spec)) = line.split_once('=') {
+                if sha1.trim().eq_ignore_ascii_case(rom_sha1) {
+                    return Ok(Self::parse(spec));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    pub fn save(&self, path: &str, rom_sha1: &str) -> io::Result<()> {
+        files::save_line(path, rom_sha1, &self.spec())
+    }
+}
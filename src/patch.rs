@@ -0,0 +1,310 @@
+use std::{
+    fs,
+    io,
+    path::Path,
+};
+use crate::chip8::MAX_ROM_SIZE;
+use crate::files::invalid;
+use crate::romfile;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: usize = 0x454F46;
+const BPS_MAGIC: &[u8] = b"BPS1";
+
+// A patch sitting next to the ROM with the same name, e.g. game.bps for
+// game.ch8. BPS is preferred when both exist.
+pub fn sidecar_for_rom(rom: &str) -> Option<String> {
+    ["bps", "ips"]
+        .iter()
+        .map(|extension| romfile::sidecar_path(rom, extension))
+        .find(|path| Path::new(path).is_file())
+}
+
+pub fn apply_file(rom: &[u8], path: &str) -> io::Result<Vec<u8>> {
+    let patch = fs::read(path)?;
+    apply(rom, &patch).map_err(|why| io::Error::new(why.kind(), format!("{}: {}", path, why)))
+}
+
+// Applies an IPS or BPS patch, telling them apart by their header
+pub fn apply(rom: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+    if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else {
+        Err(invalid("not an IPS or BPS patch"))
+    }
+}
+
+// Reads big-endian integers and raw bytes off the front of a patch
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, length: usize) -> io::Result<&'a [u8]> {
+        let bytes = self.data
+            .get(self.position..self.position + length)
+            .ok_or_else(|| invalid("patch is truncated"))?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn number(&mut self, length: usize) -> io::Result<usize> {
+        Ok(self.bytes(length)?.iter().fold(0, |value, byte| value << 8 | *byte as usize))
+    }
+
+    // BPS variable-length integers: seven bits per byte, least significant
+    // first, with the top bit marking the last byte
+    fn varint(&mut self) -> io::Result<usize> {
+        let too_large = || invalid("patch has a number too large to use");
+        let mut value: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let byte = self.number(1)?;
+            value = (byte & 0x7F).checked_mul(shift).and_then(|bits| value.checked_add(bits)).ok_or_else(too_large)?;
+            if byte & 0x80 != 0 {
+                return Ok(value);
+            }
+            shift = shift.checked_mul(0x80).ok_or_else(too_large)?;
+            value = value.checked_add(shift).ok_or_else(too_large)?;
+        }
+    }
+}
+
+// Records of (offset, bytes) or (offset, run length, byte) until "EOF",
+// optionally followed by the size to truncate the output to
+fn apply_ips(rom: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = rom.to_vec();
+    let mut reader = Reader { data: patch, position: IPS_MAGIC.len() };
+
+    loop {
+        let offset = reader.number(3)?;
+        if offset == IPS_EOF {
+            break;
+        }
+
+        let length = reader.number(2)?;
+        let (length, bytes) = if length == 0 {
+            let run = reader.number(2)?;
+            let value = reader.bytes(1)?[0];
+            (run, vec![value; run])
+        } else {
+            (length, reader.bytes(length)?.to_vec())
+        };
+
+        if offset + length > MAX_ROM_SIZE {
+            return Err(invalid("patched ROM is too large to fit in memory"));
+        }
+        if output.len() < offset + length {
+            output.resize(offset + length, 0);
+        }
+        output[offset..offset + length].copy_from_slice(&bytes);
+    }
+
+    if let Ok(size) = reader.number(3) {
+        output.truncate(size);
+    }
+
+    Ok(output)
+}
+
+// Checks the source, target and patch CRC32s in the footer, so a patch made
+// for a different ROM is refused rather than producing garbage
+fn apply_bps(rom: &[u8], patch: &[u8]) -> io::Result<Vec<u8>> {
+    if patch.len() < BPS_MAGIC.len() + 12 {
+        return Err(invalid("patch is truncated"));
+    }
+
+    let footer = patch.len() - 12;
+    let crc = |offset: usize| u32::from_le_bytes([patch[offset], patch[offset + 1], patch[offset + 2], patch[offset + 3]]);
+    let (source_crc, target_crc, patch_crc) = (crc(footer), crc(footer + 4), crc(footer + 8));
+
+    if crc32fast::hash(&patch[..footer + 8]) != patch_crc {
+        return Err(invalid("patch is corrupt (checksum mismatch)"));
+    }
+    if crc32fast::hash(rom) != source_crc {
+        return Err(invalid("patch is for a different ROM"));
+    }
+
+    let mut reader = Reader { data: &patch[..footer], position: BPS_MAGIC.len() };
+    let source_size = reader.varint()?;
+    let target_size = reader.varint()?;
+    let metadata_size = reader.varint()?;
+    reader.bytes(metadata_size)?;
+
+    if source_size != rom.len() {
+        return Err(invalid("patch is for a different ROM"));
+    }
+    // The size comes straight from the file, so check it before allocating
+    if target_size > MAX_ROM_SIZE {
+        return Err(invalid("patched ROM is too large to fit in memory"));
+    }
+
+    let mut output: Vec<u8> = Vec::with_capacity(target_size);
+    let mut source_offset: usize = 0;
+    let mut target_offset: usize = 0;
+
+    // Relative offsets are stored as magnitude << 1 | sign
+    let relative = |position: usize, data: usize| -> io::Result<usize> {
+        let delta = data >> 1;
+        let moved = if data & 1 != 0 { position.checked_sub(delta) } else { position.checked_add(delta) };
+        moved.ok_or_else(|| invalid("patch copies from outside the file"))
+    };
+
+    while reader.position < footer {
+        let data = reader.varint()?;
+        let length = (data >> 2) + 1;
+        if output.len() + length > target_size {
+            return Err(invalid("patch writes past the end of the ROM"));
+        }
+
+        match data & 3 {
+            // SourceRead: the same bytes as the ROM at this position
+            0 => {
+                let start = output.len();
+                let bytes = rom.get(start..start + length).ok_or_else(|| invalid("patch reads past the ROM"))?;
+                output.extend_from_slice(bytes);
+            }
+            // TargetRead: new bytes stored in the patch
+            1 => output.extend_from_slice(reader.bytes(length)?),
+            // SourceCopy: bytes from elsewhere in the ROM
+            2 => {
+                source_offset = relative(source_offset, reader.varint()?)?;
+                let bytes = rom
+                    .get(source_offset..source_offset + length)
+                    .ok_or_else(|| invalid("patch reads past the ROM"))?;
+                output.extend_from_slice(bytes);
+                source_offset += length;
+            }
+            // TargetCopy: bytes already written, one at a time since the
+            // ranges may overlap
+            _ => {
+                target_offset = relative(target_offset, reader.varint()?)?;
+                for _ in 0..length {
+                    let byte = *output.get(target_offset).ok_or_else(|| invalid("patch copies past the output"))?;
+                    output.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if output.len() != target_size || crc32fast::hash(&output) != target_crc {
+        return Err(invalid("patched ROM doesn't match the patch's checksum"));
+    }
+
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ips(records: &[(usize, &[u8])]) -> Vec<u8> {
+        let mut patch = IPS_MAGIC.to_vec();
+        for (offset, bytes) in records {
+            patch.extend_from_slice(&offset.to_be_bytes()[5..]);
+            patch.extend_from_slice(&bytes.len().to_be_bytes()[6..]);
+            patch.extend_from_slice(bytes);
+        }
+        patch.extend_from_slice(b"EOF");
+        patch
+    }
+
+    fn varint(mut value: usize, output: &mut Vec<u8>) {
+        loop {
+            let bits = (value & 0x7F) as u8;
+            value >>= 7;
+            if value == 0 {
+                output.push(0x80 | bits);
+                return;
+            }
+            output.push(bits);
+            value -= 1;
+        }
+    }
+
+    // A BPS patch from `source` to `target` made of the given actions, with
+    // correct checksums unless the body is tampered with afterwards
+    fn bps(source: &[u8], target_size: usize, body: &[u8], target: &[u8]) -> Vec<u8> {
+        let mut patch = BPS_MAGIC.to_vec();
+        varint(source.len(), &mut patch);
+        varint(target_size, &mut patch);
+        varint(0, &mut patch);
+        patch.extend_from_slice(body);
+        patch.extend_from_slice(&crc32fast::hash(source).to_le_bytes());
+        patch.extend_from_slice(&crc32fast::hash(target).to_le_bytes());
+        let patch_crc = crc32fast::hash(&patch);
+        patch.extend_from_slice(&patch_crc.to_le_bytes());
+        patch
+    }
+
+    // 1 2 3 4 -> 1 9 3 4 5: keep one byte, write 9, keep two, append 5
+    fn example_bps() -> Vec<u8> {
+        let mut body: Vec<u8> = Vec::new();
+        varint(0, &mut body);
+        varint(1, &mut body);
+        body.push(9);
+        varint(1 << 2, &mut body);
+        varint(1, &mut body);
+        body.push(5);
+        bps(&[1, 2, 3, 4], 5, &body, &[1, 9, 3, 4, 5])
+    }
+
+    #[test]
+    fn ips_overwrites_and_extends() {
+        let patch = ips(&[(1, &[7, 8]), (5, &[6])]);
+        assert_eq!(apply(&[0, 1, 2, 3], &patch).unwrap(), vec![0, 7, 8, 3, 0, 6]);
+    }
+
+    #[test]
+    fn ips_run_length_records_and_truncation() {
+        let mut patch = IPS_MAGIC.to_vec();
+        patch.extend_from_slice(&[0, 0, 1, 0, 0, 0, 3, 0xAA]);
+        patch.extend_from_slice(b"EOF");
+        patch.extend_from_slice(&[0, 0, 3]);
+        assert_eq!(apply(&[0, 1, 2, 3, 4, 5], &patch).unwrap(), vec![0, 0xAA, 0xAA]);
+    }
+
+    #[test]
+    fn ips_refuses_to_grow_past_memory() {
+        let patch = ips(&[(MAX_ROM_SIZE, &[1])]);
+        assert!(apply(&[0], &patch).is_err());
+    }
+
+    #[test]
+    fn bps_applies() {
+        assert_eq!(apply(&[1, 2, 3, 4], &example_bps()).unwrap(), vec![1, 9, 3, 4, 5]);
+    }
+
+    #[test]
+    fn bps_rejects_a_different_rom() {
+        assert!(apply(&[1, 2, 3, 5], &example_bps()).is_err());
+    }
+
+    #[test]
+    fn bps_rejects_a_corrupt_patch() {
+        let mut patch = example_bps();
+        patch[8] ^= 0xFF;
+        assert!(apply(&[1, 2, 3, 4], &patch).is_err());
+    }
+
+    #[test]
+    fn bps_rejects_an_endless_number() {
+        let patch = bps(&[1], 1, &[0; 16], &[1]);
+        assert!(apply(&[1], &patch).is_err());
+    }
+
+    #[test]
+    fn bps_rejects_a_huge_target() {
+        let patch = bps(&[1], usize::MAX >> 8, &[], &[1]);
+        assert!(apply(&[1], &patch).is_err());
+    }
+
+    #[test]
+    fn unknown_formats_are_rejected() {
+        assert!(apply(&[1], b"NOPE").is_err());
+    }
+}
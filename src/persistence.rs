@@ -0,0 +1,87 @@
+use macroquad::prelude::*;
+use crate::capture::Frame;
+use crate::chip8::{Machine, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::palette::Palette;
+
+pub const DEFAULT_FADE_FRAMES: u32 = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PersistenceMode {
+    // Pixels that turn off fade out over this many frames
+    Fade(u32),
+    // A pixel is shown if it was lit in this frame or the previous one
+    Blend,
+}
+
+impl PersistenceMode {
+    // "fade", "fade:<frames>" or "blend"
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "fade" => Some(PersistenceMode::Fade(DEFAULT_FADE_FRAMES)),
+            "blend" => Some(PersistenceMode::Blend),
+            _ => text.strip_prefix("fade:").and_then(|frames| frames.parse().ok()).map(PersistenceMode::Fade),
+        }
+    }
+}
+
+// Reduces the flicker caused by games erasing and redrawing sprites with XOR.
+// Works on its own copy of the framebuffer so the machine state is untouched;
+// feed it once per frame and draw the image it derives.
+pub struct PersistenceFilter {
+    mode: PersistenceMode,
+    intensity: [f32; SCREEN_WIDTH * SCREEN_HEIGHT],
+    previous: [bool; SCREEN_WIDTH * SCREEN_HEIGHT],
+}
+
+impl PersistenceFilter {
+    pub fn new(mode: PersistenceMode) -> Self {
+        Self {
+            mode,
+            intensity: [0.0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            previous: [false; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn mode(&self) -> PersistenceMode {
+        self.mode
+    }
+
+    pub fn update(&mut self, machine: &Machine) {
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let i = y * SCREEN_WIDTH + x;
+                let lit = machine.pixel(x, y);
+
+                self.intensity[i] = match self.mode {
+                    PersistenceMode::Fade(_) if lit => 1.0,
+                    PersistenceMode::Fade(frames) => (self.intensity[i] - 1.0 / frames.max(1) as f32).max(0.0),
+                    PersistenceMode::Blend => (lit || self.previous[i]) as u8 as f32,
+                };
+                self.previous[i] = lit;
+            }
+        }
+    }
+
+    // The filtered framebuffer as a one-pixel-per-cell RGBA image
+    pub fn frame(&self, palette: &Palette) -> Frame {
+        let background = palette.background();
+        let foreground = palette.foreground();
+
+        let mut rgba = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+        for intensity in self.intensity {
+            let color = Color::new(
+                background.r + (foreground.r - background.r) * intensity,
+                background.g + (foreground.g - background.g) * intensity,
+                background.b + (foreground.b - background.b) * intensity,
+                1.0,
+            );
+            rgba.extend_from_slice(&<[u8; 4]>::from(color));
+        }
+
+        Frame {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            rgba,
+        }
+    }
+}
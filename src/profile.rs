@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, HashMap};
+use crate::symbols::SymbolTable;
+
+// Number of hot loops and hot instructions listed in a report
+const TOP_N: usize = 10;
+
+// How the program used each byte of memory
+const EXECUTED: u8 = 1;
+const SPRITE: u8 = 2;
+const DATA: u8 = 4;
+
+// Groups opcodes by instruction, e.g. 0xD125 is "DXYN"
+pub fn opcode_class(opcode: u16) -> &'static str {
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => "00E0",
+            0x00EE => "00EE",
+            _ => "0NNN",
+        },
+        0x1000 => "1NNN",
+        0x2000 => "2NNN",
+        0x3000 => "3XNN",
+        0x4000 => "4XNN",
+        0x5000 => "5XY0",
+        0x6000 => "6XNN",
+        0x7000 => "7XNN",
+        0x8000 => match opcode & 0x000F {
+            0x0 => "8XY0",
+            0x1 => "8XY1",
+            0x2 => "8XY2",
+            0x3 => "8XY3",
+            0x4 => "8XY4",
+            0x5 => "8XY5",
+            0x6 => "8XY6",
+            0x7 => "8XY7",
+            0xE => "8XYE",
+            _ => "8XY?",
+        },
+        0x9000 => "9XY0",
+        0xA000 => "ANNN",
+        0xB000 => "BNNN",
+        0xC000 => "CXNN",
+        0xD000 => "DXYN",
+        0xE000 => match opcode & 0x00FF {
+            0x9E => "EX9E",
+            0xA1 => "EXA1",
+            _ => "EX??",
+        },
+        _ => match opcode & 0x00FF {
+            0x07 => "FX07",
+            0x0A => "FX0A",
+            0x15 => "FX15",
+            0x18 => "FX18",
+            0x1E => "FX1E",
+            0x29 => "FX29",
+            0x33 => "FX33",
+            0x55 => "FX55",
+            0x65 => "FX65",
+            _ => "FX??",
+        },
+    }
+}
+
+// Counts executions per address and per opcode class, which bytes were read
+// as sprites or data through I, and how often each backwards jump was taken.
+// A backwards jump from `from` to `target` marks a loop whose body is
+// target..=from.
+pub struct Profiler {
+    path: String,
+    executions: Vec<u64>,
+    usage: Vec<u8>,
+    classes: BTreeMap<&'static str, u64>,
+    loops: HashMap<(u16, u16), u64>,
+    total: u64,
+}
+
+impl Profiler {
+    // The report is written to `path` when asked for
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            executions: vec![0; 4096],
+            usage: vec![0; 4096],
+            classes: BTreeMap::new(),
+            loops: HashMap::new(),
+            total: 0,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn record_fetch(&mut self, pc: u16, opcode: u16) {
+        let pc = pc as usize % 4096;
+        self.executions[pc] += 1;
+        self.usage[pc] |= EXECUTED;
+        self.usage[(pc + 1) % 4096] |= EXECUTED;
+        *self.classes.entry(opcode_class(opcode)).or_insert(0) += 1;
+        self.total += 1;
+    }
+
+    // Called after each instruction with where it was fetched from and where
+    // execution continues
+    pub fn record_jump(&mut self, from: u16, to: u16) {
+        if to <= from {
+            *self.loops.entry((from, to)).or_insert(0) += 1;
+        }
+    }
+
+    pub fn record_sprite_read(&mut self, address: u16, length: usize) {
+        self.mark(address, length, SPRITE);
+    }
+
+    pub fn record_data_read(&mut self, address: u16, length: usize) {
+        self.mark(address, length, DATA);
+    }
+
+    fn mark(&mut self, address: u16, length: usize, usage: u8) {
+        for offset in 0..length {
+            self.usage[(address as usize + offset) % 4096] |= usage;
+        }
+    }
+
+    // A plain-text report covering the ROM loaded at 0x200
+    pub fn report(&self, rom_sha1: &str, rom_len: usize, symbols: &SymbolTable) -> String {
+        let mut output = String::new();
+
+        output.push_str(&format!("rom_sha1: {}\n", rom_sha1));
+        output.push_str(&format!("instructions: {}\n", self.total));
+
+        output.push_str("\nopcode classes:\n");
+        let mut classes: Vec<(&&str, &u64)> = self.classes.iter().collect();
+        classes.sort_by(|a, b| b.1.cmp(a.1));
+        for (class, count) in classes {
+            let share = *count as f64 * 100.0 / self.total.max(1) as f64;
+            output.push_str(&format!("  {}  {:>12}  {:5.1}%\n", class, count, share));
+        }
+
+        output.push_str("\nhot loops:\n");
+        let mut loops: Vec<(&(u16, u16), &u64)> = self.loops.iter().collect();
+        loops.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for ((from, to), count) in loops.into_iter().take(TOP_N) {
+            let body: u64 = (*to..=*from).map(|address| self.executions[address as usize % 4096]).sum();
+            output.push_str(&format!(
+                "  {}-{}  taken {:>10} times  {:>12} instructions in body\n",
+                symbols.name(*to), symbols.name(*from), count, body,
+            ));
+        }
+
+        output.push_str("\nhot instructions:\n");
+        let mut addresses: Vec<usize> = (0..4096).filter(|address| self.executions[*address] > 0).collect();
+        addresses.sort_by(|a, b| self.executions[*b].cmp(&self.executions[*a]).then(a.cmp(b)));
+        for address in addresses.into_iter().take(TOP_N) {
+            output.push_str(&format!("  {:<24}  {:>12}\n", symbols.name(address as u16), self.executions[address]));
+        }
+
+        // One character per ROM byte: X executed, S read as a sprite, D read
+        // as data, * more than one of those, . never touched
+        let rom = 0x200..(0x200 + rom_len).min(4096);
+        let count = |usage: u8| rom.clone().filter(|address| self.usage[*address] & usage != 0).count();
+        let untouched = rom.clone().filter(|address| self.usage[*address] == 0).count();
+
+        output.push_str("\nrom bytes:\n");
+        output.push_str(&format!("  executed: {}\n", count(EXECUTED)));
+        output.push_str(&format!("  sprite: {}\n", count(SPRITE)));
+        output.push_str(&format!("  data: {}\n", count(DATA)));
+        output.push_str(&format!("  untouched: {}\n", untouched));
+
+        output.push_str("\nrom map (X executed, S sprite, D data, * several, . untouched):\n");
+        for line_start in rom.clone().step_by(64) {
+            let line: String = (line_start..(line_start + 64).min(rom.end))
+                .map(|address| match self.usage[address] {
+                    0 => '.',
+                    EXECUTED => 'X',
+                    SPRITE => 'S',
+                    DATA => 'D',
+                    _ => '*',
+                })
+                .collect();
+            output.push_str(&format!("  {:#05x}  {}\n", line_start, line));
+        }
+
+        output
+    }
+}
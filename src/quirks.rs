@@ -0,0 +1,92 @@
+use std::fmt;
+use serde::Deserialize;
+
+// Behaviours that differ between CHIP-8 interpreters. Field names follow the
+// community CHIP-8 database (https://github.com/chip-8/chip-8-database).
+#[derive(Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Quirks {
+    // 8xy6/8xyE shift VX in place instead of copying VY first
+    pub shift: bool,
+    // Fx55/Fx65 advance I by X instead of X + 1
+    pub memory_increment_by_x: bool,
+    // Fx55/Fx65 leave I untouched
+    pub memory_leave_i_unchanged: bool,
+    // Sprites wrap around the screen edges instead of clipping
+    pub wrap: bool,
+    // Bnnn jumps to NNN + VX instead of NNN + V0
+    pub jump: bool,
+    // Dxyn waits for the next frame before execution continues
+    pub vblank: bool,
+    // 8xy1/8xy2/8xy3 reset VF to zero
+    pub logic: bool,
+}
+
+// The behaviour this interpreter has always had
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift: true,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: true,
+            wrap: true,
+            jump: false,
+            vblank: false,
+            logic: false,
+        }
+    }
+}
+
+impl Quirks {
+    fn fields(&self) -> [(&'static str, bool); 7] {
+        [
+            ("shift", self.shift),
+            ("memoryIncrementByX", self.memory_increment_by_x),
+            ("memoryLeaveIUnchanged", self.memory_leave_i_unchanged),
+            ("wrap", self.wrap),
+            ("jump", self.jump),
+            ("vblank", self.vblank),
+            ("logic", self.logic),
+        ]
+    }
+
+    // Parses the "name=0|1 ..." form written by Display. Missing names keep
+    // their default value.
+    pub fn parse(text: &str) -> Option<Self> {
+        let mut quirks = Self::default();
+
+        for field in text.split_whitespace() {
+            let (name, value) = field.split_once('=')?;
+            let value = match value {
+                "0" => false,
+                "1" => true,
+                _ => return None,
+            };
+
+            match name {
+                "shift" => quirks.shift = value,
+                "memoryIncrementByX" => quirks.memory_increment_by_x = value,
+                "memoryLeaveIUnchanged" => quirks.memory_leave_i_unchanged = value,
+                "wrap" => quirks.wrap = value,
+                "jump" => quirks.jump = value,
+                "vblank" => quirks.vblank = value,
+                "logic" => quirks.logic = value,
+                _ => return None,
+            }
+        }
+
+        Some(quirks)
+    }
+}
+
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<String> = self
+            .fields()
+            .iter()
+            .map(|(name, value)| format!("{}={}", name, *value as u8))
+            .collect();
+
+        write!(f, "{}", fields.join(" "))
+    }
+}
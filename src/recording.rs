@@ -0,0 +1,243 @@
+use std::{
+    borrow::Cow,
+    fs,
+    io,
+    io::Seek,
+    io::Write,
+};
+use macroquad::prelude::*;
+use crate::chip8::{Machine, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::palette::Palette;
+
+const FRAME_RATE: u64 = 60;
+
+fn color_rgb(color: Color) -> [u8; 3] {
+    [
+        (color.r * 255.0) as u8,
+        (color.g * 255.0) as u8,
+        (color.b * 255.0) as u8,
+    ]
+}
+
+// Records the framebuffer to an animated GIF using a two-entry palette, one
+// GIF frame per `decimate` emulated frames.
+pub struct GifRecorder {
+    encoder: gif::Encoder<io::BufWriter<fs::File>>,
+    scale: usize,
+    decimate: u64,
+    limit: Option<u64>,
+    frames_seen: u64,
+    delay_written: u64,
+}
+
+impl GifRecorder {
+    // `limit` is a number of emulated frames after which recording stops.
+    pub fn create(path: &str, scale: usize, palette: &Palette, decimate: u64, limit: Option<u64>) -> io::Result<Self> {
+        let scale = scale.max(1);
+        let file = io::BufWriter::new(fs::File::create(path)?);
+
+        let mut colors = Vec::with_capacity(6);
+        colors.extend_from_slice(&color_rgb(palette.background()));
+        colors.extend_from_slice(&color_rgb(palette.foreground()));
+
+        let mut encoder = gif::Encoder::new(file, (SCREEN_WIDTH * scale) as u16, (SCREEN_HEIGHT * scale) as u16, &colors)
+            .map_err(io::Error::other)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(io::Error::other)?;
+
+        Ok(Self {
+            encoder,
+            scale,
+            decimate: decimate.max(1),
+            limit,
+            frames_seen: 0,
+            delay_written: 0,
+        })
+    }
+
+    pub fn capture(&mut self, machine: &Machine) -> io::Result<()> {
+        if self.finished() {
+            return Ok(());
+        }
+
+        let frame_index = self.frames_seen;
+        self.frames_seen += 1;
+        if !frame_index.is_multiple_of(self.decimate) {
+            return Ok(());
+        }
+
+        let width = SCREEN_WIDTH * self.scale;
+        let height = SCREEN_HEIGHT * self.scale;
+
+        let mut buffer = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                buffer.push(machine.pixel(x / self.scale, y / self.scale) as u8);
+            }
+        }
+
+        // GIF delays are in hundredths of a second, which 60 fps doesn't
+        // divide evenly. Track the running total so the error never builds up.
+        let shown_until = (frame_index + self.decimate) * 100 / FRAME_RATE;
+        let delay = shown_until - self.delay_written;
+        self.delay_written = shown_until;
+
+        let frame = gif::Frame {
+            width: width as u16,
+            height: height as u16,
+            delay: delay as u16,
+            buffer: Cow::Owned(buffer),
+            ..gif::Frame::default()
+        };
+
+        self.encoder.write_frame(&frame).map_err(io::Error::other)
+    }
+
+    pub fn finished(&self) -> bool {
+        match self.limit {
+            Some(limit) => self.frames_seen >= limit,
+            None => false,
+        }
+    }
+}
+
+const SAMPLE_RATE: u32 = 44100;
+const SAMPLES_PER_FRAME: u32 = SAMPLE_RATE / FRAME_RATE as u32;
+const TONE_HZ: f32 = 440.0;
+const TONE_AMPLITUDE: i16 = 8000;
+
+// BT.601 studio-range Y'CbCr
+fn color_ycbcr(color: Color) -> [u8; 3] {
+    let (r, g, b) = (color.r, color.g, color.b);
+    let y = 16.0 + 65.481 * r + 128.553 * g + 24.966 * b;
+    let cb = 128.0 - 37.797 * r - 74.203 * g + 112.0 * b;
+    let cr = 128.0 + 112.0 * r - 93.786 * g - 18.214 * b;
+    [y.round() as u8, cb.round() as u8, cr.round() as u8]
+}
+
+fn write_wav_header(writer: &mut impl io::Write, samples: u32) -> io::Result<()> {
+    let data_size = samples * 2;
+
+    writer.write_all(b"RIFF")?;
+    writer.write_all(&(36 + data_size).to_le_bytes())?;
+    writer.write_all(b"WAVE")?;
+    writer.write_all(b"fmt ")?;
+    writer.write_all(&16u32.to_le_bytes())?;
+    writer.write_all(&1u16.to_le_bytes())?; // PCM
+    writer.write_all(&1u16.to_le_bytes())?; // mono
+    writer.write_all(&SAMPLE_RATE.to_le_bytes())?;
+    writer.write_all(&(SAMPLE_RATE * 2).to_le_bytes())?;
+    writer.write_all(&2u16.to_le_bytes())?;
+    writer.write_all(&16u16.to_le_bytes())?;
+    writer.write_all(b"data")?;
+    writer.write_all(&data_size.to_le_bytes())?;
+
+    Ok(())
+}
+
+// Records every frame losslessly as uncompressed Y4M video alongside a WAV of
+// the sound timer's tone. Each video frame is paired with exactly one frame's
+// worth of samples, so the two streams stay in sync without an encoder.
+pub struct AvRecorder {
+    video: io::BufWriter<fs::File>,
+    audio: io::BufWriter<fs::File>,
+    scale: usize,
+    background: [u8; 3],
+    foreground: [u8; 3],
+    limit: Option<u64>,
+    frames_seen: u64,
+    samples_written: u32,
+    phase: f32,
+}
+
+impl AvRecorder {
+    // Creates `<base>.y4m` and `<base>.wav`.
+    pub fn create(base: &str, scale: usize, palette: &Palette, limit: Option<u64>) -> io::Result<Self> {
+        let scale = scale.max(1);
+
+        let mut video = io::BufWriter::new(fs::File::create(format!("{}.y4m", base))?);
+        writeln!(video, "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444", SCREEN_WIDTH * scale, SCREEN_HEIGHT * scale, FRAME_RATE)?;
+
+        // Sizes are patched in when the recording is finished
+        let mut audio = io::BufWriter::new(fs::File::create(format!("{}.wav", base))?);
+        write_wav_header(&mut audio, 0)?;
+
+        Ok(Self {
+            video,
+            audio,
+            scale,
+            background: color_ycbcr(palette.background()),
+            foreground: color_ycbcr(palette.foreground()),
+            limit,
+            frames_seen: 0,
+            samples_written: 0,
+            phase: 0.0,
+        })
+    }
+
+    pub fn capture(&mut self, machine: &Machine) -> io::Result<()> {
+        if self.finished() {
+            return Ok(());
+        }
+        self.frames_seen += 1;
+
+        let width = SCREEN_WIDTH * self.scale;
+        let height = SCREEN_HEIGHT * self.scale;
+
+        self.video.write_all(b"FRAME\n")?;
+        for plane in 0..3 {
+            let mut row = Vec::with_capacity(width);
+            for y in 0..height {
+                row.clear();
+                for x in 0..width {
+                    if machine.pixel(x / self.scale, y / self.scale) {
+                        row.push(self.foreground[plane]);
+                    } else {
+                        row.push(self.background[plane]);
+                    }
+                }
+                self.video.write_all(&row)?;
+            }
+        }
+
+        // Square wave for as long as the sound timer is running
+        let sounding = machine.sound_active();
+        let mut samples = Vec::with_capacity(SAMPLES_PER_FRAME as usize * 2);
+        for _ in 0..SAMPLES_PER_FRAME {
+            let sample: i16 = match (sounding, self.phase < 0.5) {
+                (false, _) => 0,
+                (true, true) => TONE_AMPLITUDE,
+                (true, false) => -TONE_AMPLITUDE,
+            };
+            samples.extend_from_slice(&sample.to_le_bytes());
+            self.phase = (self.phase + TONE_HZ / SAMPLE_RATE as f32).fract();
+        }
+        self.audio.write_all(&samples)?;
+        self.samples_written += SAMPLES_PER_FRAME;
+
+        Ok(())
+    }
+
+    pub fn finished(&self) -> bool {
+        match self.limit {
+            Some(limit) => self.frames_seen >= limit,
+            None => false,
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.video.flush()?;
+
+        self.audio.seek(io::SeekFrom::Start(0))?;
+        write_wav_header(&mut self.audio, self.samples_written)?;
+        self.audio.flush()
+    }
+}
+
+// Dropping the recorder finalizes the WAV header
+impl Drop for AvRecorder {
+    fn drop(&mut self) {
+        if let Err(why) = self.finish() {
+            eprintln!("{}", why);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use macroquad::prelude::*;
+use crate::capture::Frame;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ScaleMode {
+    // As large as fits while keeping pixels square
+    Fit,
+    // The largest whole-number multiple that fits, so every CHIP-8 pixel is
+    // the same number of screen pixels
+    Integer,
+}
+
+impl ScaleMode {
+    pub fn parse(text: &str) -> Option<Self> {
+        match text {
+            "fit" => Some(ScaleMode::Fit),
+            "integer" => Some(ScaleMode::Integer),
+            _ => None,
+        }
+    }
+
+    pub fn next(self) -> Self {
+        match self {
+            ScaleMode::Fit => ScaleMode::Integer,
+            ScaleMode::Integer => ScaleMode::Fit,
+        }
+    }
+}
+
+// Draws the framebuffer as a single nearest-filtered texture, centred in the
+// window with letterbox borders so the aspect ratio is kept whatever the
+// window size. The texture is only re-uploaded when given a new frame, and is
+// recreated if the frame size changes (e.g. switching to a 128x64 mode).
+pub struct ScreenRenderer {
+    texture: Option<Texture2D>,
+    scale_mode: ScaleMode,
+}
+
+impl ScreenRenderer {
+    pub fn new(scale_mode: ScaleMode) -> Self {
+        Self {
+            texture: None,
+            scale_mode,
+        }
+    }
+
+    pub fn set_scale_mode(&mut self, scale_mode: ScaleMode) {
+        self.scale_mode = scale_mode;
+    }
+
+    pub fn upload(&mut self, frame: &Frame) {
+        let (width, height) = (frame.width as u16, frame.height as u16);
+
+        match self.texture {
+            Some(ref texture) if texture.width() as u16 == width && texture.height() as u16 == height => {
+                texture.update_from_bytes(width as u32, height as u32, &frame.rgba);
+            }
+            _ => {
+                let texture = Texture2D::from_rgba8(width, height, &frame.rgba);
+                texture.set_filter(FilterMode::Nearest);
+                self.texture = Some(texture);
+            }
+        }
+    }
+
+    // The screen-space rectangle the framebuffer occupies
+    pub fn viewport(&self) -> Rect {
+        let (width, height) = match self.texture {
+            Some(ref texture) => (texture.width(), texture.height()),
+            None => return Rect::new(0.0, 0.0, screen_width(), screen_height()),
+        };
+
+        let mut scale = (screen_width() / width).min(screen_height() / height);
+        if self.scale_mode == ScaleMode::Integer && scale >= 1.0 {
+            scale = scale.floor();
+        }
+
+        let (w, h) = (width * scale, height * scale);
+        Rect::new(((screen_width() - w) / 2.0).floor(), ((screen_height() - h) / 2.0).floor(), w, h)
+    }
+
+    pub fn draw(&self) {
+        if let Some(ref texture) = self.texture {
+            let viewport = self.viewport();
+            let params = DrawTextureParams {
+                dest_size: Some(viewport.size()),
+                ..Default::default()
+            };
+            draw_texture_ex(texture, viewport.x, viewport.y, WHITE, params);
+        }
+    }
+}
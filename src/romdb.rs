@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io,
+    path::Path,
+};
+use macroquad::prelude::*;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use crate::files::invalid;
+use crate::palette::{parse_hex_color, Palette};
+use crate::quirks::Quirks;
+
+// Subsets of programs.json and platforms.json from the community CHIP-8
+// database (https://github.com/chip-8/chip-8-database), covering the ROMs
+// shipped in roms/, the platforms this interpreter can run and those whose
+// quirks are guessed from file extensions.
+const PROGRAMS: &str = include_str!("../data/programs.json");
+const PLATFORMS: &str = include_str!("../data/platforms.json");
+
+// Entries in this file, using the programs.json format, take precedence over
+// the built-in database.
+pub const USER_DATABASE: &str = "romdb.json";
+
+// The platform whose speed ROMs missing from the database run at
+const DEFAULT_PLATFORM: &str = "modernChip8";
+
+// Platforms whose whole instruction set this interpreter runs, in the order
+// the database's entries are matched against
+const RUNNABLE_PLATFORMS: [&str; 2] = ["originalChip8", "modernChip8"];
+
+#[derive(Deserialize)]
+struct Program {
+    title: String,
+    roms: HashMap<String, RomEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RomEntry {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<u32>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, Map<String, Value>>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    colors: Option<Colors>,
+}
+
+#[derive(Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Platform {
+    id: String,
+    default_tickrate: u32,
+    quirks: Map<String, Value>,
+}
+
+// Everything the database knows about one ROM, resolved for a platform this
+// interpreter supports.
+pub struct RomSettings {
+    pub title: String,
+    pub platform: String,
+    pub quirks: Quirks,
+    pub tick_rate: u32,
+    // Host keys bound to CHIP-8 keys for the game's actions
+    pub keys: Vec<(u8, KeyCode)>,
+    pub palette: Option<Palette>,
+}
+
+// Host keys standing in for the database's game actions
+fn action_keycode(action: &str) -> Option<KeyCode> {
+    match action {
+        "up" => Some(KeyCode::Up),
+        "down" => Some(KeyCode::Down),
+        "left" => Some(KeyCode::Left),
+        "right" => Some(KeyCode::Right),
+        "a" => Some(KeyCode::Space),
+        "b" => Some(KeyCode::LeftShift),
+        _ => None,
+    }
+}
+
+fn platforms() -> Vec<Platform> {
+    serde_json::from_str(PLATFORMS).expect("built-in platforms.json is valid")
+}
+
+// Instructions per frame for ROMs the database doesn't know
+pub fn default_tick_rate() -> u32 {
+    platforms()
+        .iter()
+        .find(|platform| platform.id == DEFAULT_PLATFORM)
+        .map(|platform| platform.default_tickrate)
+        .expect("built-in platforms.json has the default platform")
+}
+
+fn find_rom(programs: Vec<Program>, rom_sha1: &str) -> Option<(String, RomEntry)> {
+    programs.into_iter().find_map(|program| {
+        let title = program.title;
+        program.roms.into_iter().find(|(sha1, _)| sha1 == rom_sha1).map(|(_, entry)| (title, entry))
+    })
+}
+
+fn load_user_programs(path: &str) -> io::Result<Vec<Program>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    serde_json::from_str(&text).map_err(|why| invalid(format!("{}: {}", path, why)))
+}
+
+// Looks a ROM up by its SHA-1, first in the user database at `user_path` and
+// then in the built-in one. Returns None for unknown ROMs and for ROMs that
+// only run on platforms this interpreter doesn't support.
+pub fn lookup(rom_sha1: &str, user_path: &str) -> io::Result<Option<RomSettings>> {
+    let rom_sha1 = rom_sha1.to_lowercase();
+
+    let found = match find_rom(load_user_programs(user_path)?, &rom_sha1) {
+        Some(found) => Some(found),
+        None => find_rom(serde_json::from_str(PROGRAMS).expect("built-in programs.json is valid"), &rom_sha1),
+    };
+
+    let (title, entry) = match found {
+        Some(found) => found,
+        None => return Ok(None),
+    };
+
+    let platforms = platforms();
+
+    let platform = match entry
+        .platforms
+        .iter()
+        .filter(|id| RUNNABLE_PLATFORMS.contains(&id.as_str()))
+        .find_map(|id| platforms.iter().find(|platform| &platform.id == id))
+    {
+        Some(platform) => platform,
+        None => return Ok(None),
+    };
+
+    let mut quirks = platform.quirks.clone();
+    if let Some(overrides) = entry.quirky_platforms.get(&platform.id) {
+        quirks.extend(overrides.clone());
+    }
+    let quirks: Quirks = serde_json::from_value(Value::Object(quirks))
+        .map_err(|why| invalid(format!("bad quirks for {}: {}", title, why)))?;
+
+    let mut keys: Vec<(u8, KeyCode)> = entry
+        .keys
+        .iter()
+        .filter_map(|(action, key)| action_keycode(action).map(|keycode| (*key & 0xF, keycode)))
+        .collect();
+    keys.sort_by_key(|(key, _)| *key);
+
+    let palette = entry.colors.and_then(|colors| {
+        let pixels: Option<Vec<Color>> = colors.pixels.iter().map(|pixel| parse_hex_color(pixel)).collect();
+        Palette::from_colors(&pixels?)
+    });
+
+    Ok(Some(RomSettings {
+        title,
+        platform: platform.id.clone(),
+        quirks,
+        tick_rate: entry.tickrate.unwrap_or(platform.default_tickrate),
+        keys,
+        palette,
+    }))
+}
+
+// Settings guessed from the ROM's file extension, for ROMs the database
+// doesn't know, using the platform's quirks and tick rate from
+// platforms.json. .ch8 files come from every platform, so they keep this
+// interpreter's defaults. SUPER-CHIP and XO-CHIP instructions aren't
+// supported, but games sticking to the CHIP-8 subset still depend on their
+// platform's quirks.
+pub fn guess_from_extension(rom_name: &str) -> Option<RomSettings> {
+    let path = Path::new(rom_name);
+    let id = match path.extension()?.to_string_lossy().to_lowercase().as_str() {
+        "sc8" => "superchip",
+        "xo8" => "xochip",
+        _ => return None,
+    };
+
+    let platform = platforms().into_iter().find(|platform| platform.id == id)?;
+    let quirks: Quirks = serde_json::from_value(Value::Object(platform.quirks)).expect("built-in platforms.json has valid quirks");
+
+    Some(RomSettings {
+        title: path.file_stem()?.to_string_lossy().into_owned(),
+        platform: platform.id,
+        quirks,
+        tick_rate: platform.default_tickrate,
+        keys: Vec::new(),
+        palette: None,
+    })
+}
@@ -0,0 +1,248 @@
+use std::{
+    fs,
+    io,
+    io::Read,
+    path::Path,
+};
+use zip::ZipArchive;
+use crate::chip8::MAX_ROM_SIZE;
+use crate::files::invalid;
+
+// File extensions recognised as ROMs, inside archives and directories
+pub const EXTENSIONS: [&str; 3] = ["ch8", "sc8", "xo8"];
+
+pub fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_lowercase())
+        .is_some_and(|extension| EXTENSIONS.contains(&extension.as_str()))
+}
+
+fn is_archive_name(name: &str) -> bool {
+    name.to_lowercase().ends_with(".zip")
+}
+
+// Splits "games.zip:pong.ch8" into the archive and the entry. A bare
+// "games.zip" has no entry and means the only ROM inside.
+fn split_archive(path: &str) -> Option<(&str, Option<&str>)> {
+    if is_archive_name(path) {
+        return Some((path, None));
+    }
+
+    // Searched for in the original bytes, since lower-casing can change where
+    // things are. The match is ASCII, so both slices start on a character.
+    let split = path.as_bytes().windows(5).position(|window| window.eq_ignore_ascii_case(b".zip:"))? + ".zip".len();
+    Some((&path[..split], Some(&path[split + 1..])))
+}
+
+// The file on disk holding the ROM, which for archive entries is the archive
+pub fn file_path(path: &str) -> &str {
+    match split_archive(path) {
+        Some((archive, _)) => archive,
+        None => path,
+    }
+}
+
+// A file kept next to the ROM with the given extension, such as its symbols
+// or save state. For a ROM inside an archive it goes next to the archive,
+// named after the entry: games.zip:pong.ch8 has pong.sym beside games.zip.
+pub fn sidecar_path(path: &str, extension: &str) -> String {
+    let sidecar = match split_archive(path) {
+        Some((archive, Some(entry))) => match Path::new(entry).file_name() {
+            Some(name) => Path::new(archive).with_file_name(name),
+            None => Path::new(archive).to_path_buf(),
+        },
+        _ => Path::new(path).to_path_buf(),
+    };
+    sidecar.with_extension(extension).to_string_lossy().into_owned()
+}
+
+fn open_archive(path: &str) -> io::Result<ZipArchive<fs::File>> {
+    ZipArchive::new(fs::File::open(path)?).map_err(|why| invalid(format!("{}: {}", path, why)))
+}
+
+// The ROMs in an archive, by their full names inside it
+pub fn archive_entries(path: &str) -> io::Result<Vec<String>> {
+    let archive = open_archive(path)?;
+    let mut entries: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/') && is_rom_name(name))
+        .map(String::from)
+        .collect();
+    entries.sort();
+    Ok(entries)
+}
+
+// Picks the archive entry `path` refers to. An entry can be named by its full
+// path inside the archive or, if that is unambiguous, just its file name.
+fn select_entry(archive: &str, entry: Option<&str>) -> io::Result<String> {
+    let entries = archive_entries(archive)?;
+
+    let found: Vec<&String> = match entry {
+        Some(entry) => match entries.iter().find(|name| name.as_str() == entry) {
+            Some(name) => vec![name],
+            None => entries
+                .iter()
+                .filter(|name| Path::new(name.as_str()).file_name().is_some_and(|file_name| file_name == entry))
+                .collect(),
+        },
+        None => entries.iter().collect(),
+    };
+
+    match (found.as_slice(), entry) {
+        ([name], _) => Ok(name.to_string()),
+        ([], Some(entry)) => Err(invalid(format!("{} has no ROM named {}", archive, entry))),
+        ([], None) => Err(invalid(format!("{} has no ROMs in it", archive))),
+        (names, _) => {
+            let names: Vec<String> = names.iter().map(|name| format!("{}:{}", archive, name)).collect();
+            Err(invalid(format!("{} holds several ROMs, pick one of:\n  {}", archive, names.join("\n  "))))
+        }
+    }
+}
+
+// The ROM's own file name, which for an archive is the entry's
+pub fn rom_name(path: &str) -> io::Result<String> {
+    let name = match split_archive(path) {
+        Some((archive, entry)) => select_entry(archive, entry)?,
+        None => path.to_string(),
+    };
+
+    Ok(Path::new(&name).file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or(name))
+}
+
+// Reads a ROM from a plain file or from inside a zip archive
+pub fn read(path: &str) -> io::Result<Vec<u8>> {
+    if Path::new(path).is_dir() {
+        return Err(invalid(format!("{} is a directory, name a ROM inside it", path)));
+    }
+
+    let (archive, entry) = match split_archive(path) {
+        Some(split) => split,
+        None => return fs::read(path),
+    };
+
+    let name = select_entry(archive, entry)?;
+    let mut zip = open_archive(archive)?;
+    let file = zip.by_name(&name).map_err(|why| invalid(format!("{}:{}: {}", archive, name, why)))?;
+    if file.size() > MAX_ROM_SIZE as u64 {
+        return Err(invalid(format!("{}:{} is {} bytes, too large to fit in memory", archive, name, file.size())));
+    }
+
+    // The size in the archive could be wrong, so never read more than a byte
+    // past what fits; anything that long is refused when the ROM is loaded
+    let mut rom: Vec<u8> = Vec::new();
+    file.take(MAX_ROM_SIZE as u64 + 1).read_to_end(&mut rom)?;
+    Ok(rom)
+}
+
+// Every ROM in a directory, not descending into subdirectories. Archives are
+// opened and each ROM inside listed as "archive.zip:entry".
+pub fn list(dir: &str) -> io::Result<Vec<String>> {
+    let mut roms: Vec<String> = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        let path = path.to_string_lossy().into_owned();
+        if is_rom_name(&path) {
+            roms.push(path);
+        } else if is_archive_name(&path) {
+            // A broken archive shouldn't hide everything else in the folder
+            if let Ok(entries) = archive_entries(&path) {
+                roms.extend(entries.iter().map(|entry| format!("{}:{}", path, entry)));
+            }
+        }
+    }
+
+    roms.sort();
+    Ok(roms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::temp_path;
+    use std::io::Write;
+    use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+    // Writes a zip holding each (name, contents) pair and returns its path
+    fn temp_zip(name: &str, entries: &[(&str, &[u8])]) -> String {
+        let path = temp_path(name, "zip");
+
+        let mut zip = ZipWriter::new(fs::File::create(&path).unwrap());
+        let options = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        for (entry, contents) in entries {
+            zip.start_file(*entry, options).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        zip.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn split_archive_finds_the_entry() {
+        assert_eq!(split_archive("roms/games.zip"), Some(("roms/games.zip", None)));
+        assert_eq!(split_archive("roms/games.zip:pong.ch8"), Some(("roms/games.zip", Some("pong.ch8"))));
+        assert_eq!(split_archive("roms/pong.ch8"), None);
+    }
+
+    #[test]
+    fn split_archive_ignores_case_without_moving_the_split() {
+        assert_eq!(split_archive("roms/Games.ZIP:Pong.ch8"), Some(("roms/Games.ZIP", Some("Pong.ch8"))));
+        // 'İ' grows when lower-cased, which used to shift the split point
+        assert_eq!(split_archive("İİ/games.zip:pong.ch8"), Some(("İİ/games.zip", Some("pong.ch8"))));
+    }
+
+    #[test]
+    fn sidecars_of_archive_entries_sit_next_to_the_archive() {
+        assert_eq!(sidecar_path("roms/pong.ch8", "sym"), "roms/pong.sym");
+        assert_eq!(sidecar_path("roms/games.zip:pong.ch8", "sym"), "roms/pong.sym");
+        assert_eq!(sidecar_path("roms/games.zip:demos/maze.ch8", "state"), "roms/maze.state");
+    }
+
+    #[test]
+    fn select_entry_by_full_or_file_name() {
+        let path = temp_zip("select", &[("demos/maze.ch8", &[0x12, 0x00]), ("pong.ch8", &[0x00, 0xE0]), ("readme.txt", b"hi")]);
+
+        assert_eq!(select_entry(&path, Some("demos/maze.ch8")).unwrap(), "demos/maze.ch8");
+        assert_eq!(select_entry(&path, Some("maze.ch8")).unwrap(), "demos/maze.ch8");
+        assert!(select_entry(&path, Some("readme.txt")).is_err());
+        assert!(select_entry(&path, None).is_err());
+        assert_eq!(read(&format!("{}:pong.ch8", path)).unwrap(), vec![0x00, 0xE0]);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn select_entry_rejects_ambiguous_names() {
+        let path = temp_zip("ambiguous", &[("a/pong.ch8", &[1]), ("b/pong.ch8", &[2])]);
+
+        assert!(select_entry(&path, Some("pong.ch8")).is_err());
+        assert_eq!(select_entry(&path, Some("b/pong.ch8")).unwrap(), "b/pong.ch8");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn a_lone_rom_needs_no_entry_name() {
+        let path = temp_zip("lone", &[("pong.ch8", &[0x00, 0xE0])]);
+
+        assert_eq!(read(&path).unwrap(), vec![0x00, 0xE0]);
+        assert_eq!(rom_name(&path).unwrap(), "pong.ch8");
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn oversized_entries_are_not_read() {
+        let big = vec![0; MAX_ROM_SIZE + 1];
+        let path = temp_zip("oversized", &[("big.ch8", &big)]);
+
+        assert!(read(&path).is_err());
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,193 @@
+use std::{
+    fs,
+    io,
+};
+use crate::files::invalid;
+use crate::romfile;
+
+const MAGIC: &str = "rustchip8-state 1";
+
+// Everything the program itself can observe: memory, the CPU, the timers and
+// the display. Settings such as quirks and the palette aren't included.
+//
+//   rustchip8-state 1
+//   rom_sha1 2f1d6ad0b5e4a1...
+//   pc 228
+//   index 2a0
+//   ...
+//   memory 00e0a22a...
+#[derive(Clone)]
+pub struct SaveState {
+    pub rom_sha1: String,
+    pub memory: Vec<u8>,
+    pub registers: [u8; 16],
+    pub pc: u16,
+    pub index: u16,
+    pub stack: [u16; 16],
+    pub sp: u8,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub display: Vec<u64>,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok()).collect()
+}
+
+impl SaveState {
+    // States live next to the ROM with a .state extension
+    pub fn path_for_rom(rom: &str) -> String {
+        romfile::sidecar_path(rom, "state")
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut output = String::new();
+
+        output.push_str(&format!("{}\n", MAGIC));
+        output.push_str(&format!("rom_sha1 {}\n", self.rom_sha1));
+        output.push_str(&format!("pc {:x}\n", self.pc));
+        output.push_str(&format!("index {:x}\n", self.index));
+        output.push_str(&format!("sp {:x}\n", self.sp));
+        output.push_str(&format!("delay_timer {:x}\n", self.delay_timer));
+        output.push_str(&format!("sound_timer {:x}\n", self.sound_timer));
+        output.push_str(&format!("registers {}\n", hex(&self.registers)));
+        let stack: Vec<String> = self.stack.iter().map(|address| format!("{:x}", address)).collect();
+        output.push_str(&format!("stack {}\n", stack.join(" ")));
+        let display: Vec<String> = self.display.iter().map(|row| format!("{:x}", row)).collect();
+        output.push_str(&format!("display {}\n", display.join(" ")));
+        output.push_str(&format!("memory {}\n", hex(&self.memory)));
+
+        fs::write(path, output)
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        let mut lines = text.lines();
+
+        if lines.next() != Some(MAGIC) {
+            return Err(invalid(format!("{} is not a save state", path)));
+        }
+
+        let mut state = Self {
+            rom_sha1: String::new(),
+            memory: vec![0; 4096],
+            registers: [0; 16],
+            pc: 0x200,
+            index: 0,
+            stack: [0; 16],
+            sp: 0,
+            delay_timer: 0,
+            sound_timer: 0,
+            display: Vec::new(),
+        };
+
+        for line in lines {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let bad = || invalid(format!("{}: bad {}", path, key));
+
+            match key {
+                "rom_sha1" => state.rom_sha1 = value.to_string(),
+                "pc" => state.pc = u16::from_str_radix(value, 16).map_err(|_| bad())?,
+                "index" => state.index = u16::from_str_radix(value, 16).map_err(|_| bad())?,
+                "sp" => state.sp = u8::from_str_radix(value, 16).map_err(|_| bad())?,
+                "delay_timer" => state.delay_timer = u8::from_str_radix(value, 16).map_err(|_| bad())?,
+                "sound_timer" => state.sound_timer = u8::from_str_radix(value, 16).map_err(|_| bad())?,
+                "registers" => {
+                    let registers = from_hex(value).ok_or_else(bad)?;
+                    state.registers = registers.try_into().map_err(|_| bad())?;
+                }
+                "stack" => {
+                    let stack: Result<Vec<u16>, _> = value.split_whitespace().map(|address| u16::from_str_radix(address, 16)).collect();
+                    state.stack = stack.map_err(|_| bad())?.try_into().map_err(|_| bad())?;
+                }
+                "display" => {
+                    let display: Result<Vec<u64>, _> = value.split_whitespace().map(|row| u64::from_str_radix(row, 16)).collect();
+                    state.display = display.map_err(|_| bad())?;
+                }
+                "memory" => {
+                    state.memory = from_hex(value).filter(|memory| memory.len() == 4096).ok_or_else(bad)?;
+                }
+                _ => {}
+            }
+        }
+
+        // Anything out of range would make the next CALL, RET or fetch read
+        // outside the stack or memory
+        if state.sp > 15 || state.pc > 0xFFE || state.index > 0xFFF || state.stack.iter().any(|address| *address > 0xFFE) {
+            return Err(invalid(format!("{}: registers out of range", path)));
+        }
+
+        Ok(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::files::temp_path;
+
+    fn example() -> SaveState {
+        let mut memory = vec![0; 4096];
+        memory[0x200] = 0x12;
+        memory[0xFFF] = 0xAB;
+        let mut stack = [0; 16];
+        stack[1] = 0x2F0;
+
+        SaveState {
+            rom_sha1: String::from("2f1d6ad0b5e4a1"),
+            memory,
+            registers: [7; 16],
+            pc: 0x228,
+            index: 0x2A0,
+            stack,
+            sp: 1,
+            delay_timer: 30,
+            sound_timer: 2,
+            display: vec![1, 0, u64::MAX],
+        }
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = temp_path("round-trip", "state");
+        let state = example();
+        state.save(&path).unwrap();
+        let loaded = SaveState::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.rom_sha1, state.rom_sha1);
+        assert_eq!(loaded.memory, state.memory);
+        assert_eq!(loaded.registers, state.registers);
+        assert_eq!((loaded.pc, loaded.index, loaded.sp), (state.pc, state.index, state.sp));
+        assert_eq!(loaded.stack, state.stack);
+        assert_eq!((loaded.delay_timer, loaded.sound_timer), (state.delay_timer, state.sound_timer));
+        assert_eq!(loaded.display, state.display);
+    }
+
+    #[test]
+    fn rejects_out_of_range_registers() {
+        let path = temp_path("out-of-range", "state");
+        for edit in [|state: &mut SaveState| state.sp = 16, |state: &mut SaveState| state.pc = 0xFFF, |state: &mut SaveState| state.index = 0x1000] {
+            let mut state = example();
+            edit(&mut state);
+            state.save(&path).unwrap();
+            assert!(SaveState::load(&path).is_err());
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let path = temp_path("not-a-state", "state");
+        fs::write(&path, "hello\n").unwrap();
+        assert!(SaveState::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
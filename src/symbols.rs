@@ -0,0 +1,187 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io,
+};
+use crate::files::invalid;
+use crate::romfile;
+
+// Labels for ROM addresses and ranges that hold data rather than code.
+//
+// Two line formats are accepted and can be mixed. The plain one, which is also
+// what gets saved:
+//
+//   # comment
+//   0x2a6 draw_player
+//   data 0x300 0x33f player_sprites
+//
+// and Octo-style constants, so a symbol list can be shared with Octo sources:
+//
+//   :const draw_player 0x2A6
+//   :const draw_enemy 678
+//
+// Addresses in the plain format are hex with or without 0x; as in Octo,
+// constants are decimal unless they have the prefix. A data range's name is
+// optional; when given it also labels the start.
+#[derive(Default)]
+pub struct SymbolTable {
+    labels: BTreeMap<u16, String>,
+    data: Vec<(u16, u16)>,
+}
+
+// Further than this past a label, an address is shown in plain hex
+const MAX_LABEL_OFFSET: u16 = 0x100;
+
+// Numbers with a 0x prefix are always hex; `radix` applies to the rest
+fn parse_address(text: &str, radix: u32) -> Option<u16> {
+    let address = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => u16::from_str_radix(text, radix),
+    };
+    address.ok().filter(|address| *address < 0x1000)
+}
+
+impl SymbolTable {
+    pub fn parse(text: &str) -> io::Result<Self> {
+        let mut symbols = Self::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let bad_line = || invalid(format!("line {}: bad symbol", number + 1));
+
+            let line = line.split('#').next().unwrap_or("");
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            match fields.as_slice() {
+                [] => {}
+                [":const", name, address] => {
+                    symbols.labels.insert(parse_address(address, 10).ok_or_else(bad_line)?, name.to_string());
+                }
+                ["data", start, end, name @ ..] if name.len() <= 1 => {
+                    let start = parse_address(start, 16).ok_or_else(bad_line)?;
+                    let end = parse_address(end, 16).ok_or_else(bad_line)?;
+                    symbols.data.push((start.min(end), start.max(end)));
+                    if let Some(name) = name.first() {
+                        symbols.labels.insert(start.min(end), name.to_string());
+                    }
+                }
+                [address, name] => {
+                    symbols.labels.insert(parse_address(address, 16).ok_or_else(bad_line)?, name.to_string());
+                }
+                _ => return Err(bad_line()),
+            }
+        }
+
+        Ok(symbols)
+    }
+
+    pub fn load(path: &str) -> io::Result<Self> {
+        let text = fs::read_to_string(path)?;
+        Self::parse(&text).map_err(|why| io::Error::new(why.kind(), format!("{}: {}", path, why)))
+    }
+
+    // Symbols live next to the ROM with a .sym extension
+    pub fn path_for_rom(rom: &str) -> String {
+        romfile::sidecar_path(rom, "sym")
+    }
+
+    pub fn load_for_rom(rom: &str) -> io::Result<Option<Self>> {
+        match Self::load(&Self::path_for_rom(rom)) {
+            Ok(symbols) => Ok(Some(symbols)),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        let mut output = String::new();
+
+        for (start, end) in self.data.iter() {
+            match self.labels.get(start) {
+                Some(name) => output.push_str(&format!("data {:#05x} {:#05x} {}\n", start, end, name)),
+                None => output.push_str(&format!("data {:#05x} {:#05x}\n", start, end)),
+            }
+        }
+        for (address, name) in self.labels.iter() {
+            if !self.data.iter().any(|(start, _)| start == address) {
+                output.push_str(&format!("{:#05x} {}\n", address, name));
+            }
+        }
+
+        fs::write(path, output)
+    }
+
+    pub fn is_data(&self, address: u16) -> bool {
+        self.data.iter().any(|(start, end)| (*start..=*end).contains(&address))
+    }
+
+    pub fn label(&self, address: u16) -> Option<&str> {
+        self.labels.get(&address).map(String::as_str)
+    }
+
+    // Whether a data range starts or ends between `from` and `to`, so that
+    // they fall in different parts of the ROM
+    fn crosses_data(&self, from: u16, to: u16) -> bool {
+        self.data.iter().any(|(start, end)| (from < *start && *start <= to) || (from <= *end && *end < to))
+    }
+
+    // The nearest label at or before `address` plus an offset, e.g.
+    // "draw_player+4", or plain hex when no label is close by or a data
+    // range lies in between
+    pub fn name(&self, address: u16) -> String {
+        match self.labels.range(..=address).next_back() {
+            Some((start, name)) if *start == address => name.clone(),
+            Some((start, name)) if address - start <= MAX_LABEL_OFFSET && !self.crosses_data(*start, address) => {
+                format!("{}+{}", name, address - start)
+            }
+            _ => format!("{:#05x}", address),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_plain_labels_and_data() {
+        let symbols = SymbolTable::parse("# sprites\n0x2a6 draw_player\n2b0 loop\ndata 0x300 0x33f sprites\ndata 350 340\n").unwrap();
+        assert_eq!(symbols.label(0x2A6), Some("draw_player"));
+        assert_eq!(symbols.label(0x2B0), Some("loop"));
+        assert_eq!(symbols.label(0x300), Some("sprites"));
+        assert!(symbols.is_data(0x33F));
+        assert!(symbols.is_data(0x345));
+        assert!(!symbols.is_data(0x2FF));
+    }
+
+    #[test]
+    fn octo_constants_are_decimal_unless_prefixed() {
+        let symbols = SymbolTable::parse(":const decimal 678\n:const hex 0x2B0\n").unwrap();
+        assert_eq!(symbols.label(678), Some("decimal"));
+        assert_eq!(symbols.label(0x2B0), Some("hex"));
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        assert!(SymbolTable::parse("0x2a6").is_err());
+        assert!(SymbolTable::parse("zzz name").is_err());
+        assert!(SymbolTable::parse("0x1000 past_memory").is_err());
+        assert!(SymbolTable::parse(":const name 4096").is_err());
+    }
+
+    #[test]
+    fn names_addresses_from_the_nearest_label() {
+        let symbols = SymbolTable::parse("0x200 start\n0x210 draw\n").unwrap();
+        assert_eq!(symbols.name(0x210), "draw");
+        assert_eq!(symbols.name(0x214), "draw+4");
+        assert_eq!(symbols.name(0x100), "0x100");
+    }
+
+    #[test]
+    fn offsets_stay_near_the_label_and_out_of_data() {
+        let symbols = SymbolTable::parse("0x200 start\ndata 0x220 0x22f\n0x400 far\n").unwrap();
+        assert_eq!(symbols.name(0x21f), "start+31");
+        assert_eq!(symbols.name(0x224), "0x224");
+        assert_eq!(symbols.name(0x230), "0x230");
+        assert_eq!(symbols.name(0x400 + MAX_LABEL_OFFSET + 1), "0x501");
+    }
+}
@@ -0,0 +1,223 @@
+use std::{
+    cell::RefCell,
+    io,
+    io::Write,
+    rc::Rc,
+    thread,
+    time,
+};
+use crossterm::{
+    cursor,
+    event,
+    event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    queue,
+    style,
+    terminal,
+};
+use macroquad::input::KeyCode as HostKeyCode;
+use crate::chip8::{Machine, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::input::InputSource;
+use crate::keymap::{self, Keymap};
+
+const FRAME_TIME: time::Duration = time::Duration::from_micros(16_667);
+
+// Terminals that can't report key releases only send repeated presses while a
+// key is held, so a press is held for this many frames after the last one.
+const HOLD_FRAMES: u32 = 8;
+
+// Key events read by the frontend this frame, for the input source. The
+// frontend reads the terminal itself so Escape and Ctrl+C quit whatever the
+// input is, e.g. while a movie plays.
+pub type TerminalKeys = Rc<RefCell<Vec<KeyEvent>>>;
+
+fn is_quit(key: &KeyEvent) -> bool {
+    let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+    key.code == KeyCode::Esc || ctrl_c
+}
+
+// Translates a terminal key into the host key name used by keymap files
+fn host_key(code: KeyCode) -> Option<HostKeyCode> {
+    match code {
+        KeyCode::Char(' ') => Some(HostKeyCode::Space),
+        KeyCode::Char(c) if c.is_ascii_digit() => keymap::key_from_name(&format!("Key{}", c)),
+        KeyCode::Char(c) => keymap::key_from_name(&c.to_string()),
+        KeyCode::Up => Some(HostKeyCode::Up),
+        KeyCode::Down => Some(HostKeyCode::Down),
+        KeyCode::Left => Some(HostKeyCode::Left),
+        KeyCode::Right => Some(HostKeyCode::Right),
+        KeyCode::Enter => Some(HostKeyCode::Enter),
+        KeyCode::Tab => Some(HostKeyCode::Tab),
+        _ => None,
+    }
+}
+
+// Reads keys from a terminal in raw mode, using the same keymap as the window.
+pub struct TerminalInput {
+    keymap: Keymap,
+    keys: TerminalKeys,
+    held: [u32; 16],
+    // Whether the terminal reports key releases
+    releases: bool,
+}
+
+impl TerminalInput {
+    pub fn new(keymap: Keymap, keys: TerminalKeys) -> Self {
+        Self {
+            keymap,
+            keys,
+            held: [0; 16],
+            releases: false,
+        }
+    }
+
+    fn handle(&mut self, key: KeyEvent) {
+        let host_key = match host_key(key.code) {
+            Some(host_key) => host_key,
+            None => return,
+        };
+
+        if key.kind == KeyEventKind::Release {
+            self.releases = true;
+        }
+
+        for chip8_key in 0..16 {
+            if self.keymap.keys_for(chip8_key).contains(&host_key) {
+                self.held[chip8_key as usize] = match key.kind {
+                    KeyEventKind::Release => 0,
+                    _ if self.releases => u32::MAX,
+                    _ => HOLD_FRAMES,
+                };
+            }
+        }
+    }
+}
+
+impl InputSource for TerminalInput {
+    fn poll(&mut self, _frame: u64) -> [bool; 16] {
+        if !self.releases {
+            for held in self.held.iter_mut() {
+                *held = held.saturating_sub(1);
+            }
+        }
+
+        let keys: Vec<KeyEvent> = self.keys.borrow_mut().drain(..).collect();
+        for key in keys {
+            self.handle(key);
+        }
+
+        self.held.map(|held| held > 0)
+    }
+
+    fn keymap_mut(&mut self) -> Option<&mut Keymap> {
+        Some(&mut self.keymap)
+    }
+}
+
+fn terminal_color(color: macroquad::color::Color) -> style::Color {
+    let [r, g, b, _]: [u8; 4] = color.into();
+    style::Color::Rgb { r, g, b }
+}
+
+// Two pixels per character cell, stacked vertically
+fn draw(out: &mut impl Write, machine: &Machine) -> io::Result<()> {
+    let palette = machine.palette();
+    queue!(
+        out,
+        style::SetForegroundColor(terminal_color(palette.foreground())),
+        style::SetBackgroundColor(terminal_color(palette.background())),
+    )?;
+
+    for cell_row in 0..SCREEN_HEIGHT / 2 {
+        let top = machine.display().row(cell_row * 2);
+        let bottom = machine.display().row(cell_row * 2 + 1);
+
+        let line: String = (0..SCREEN_WIDTH)
+            .map(|x| {
+                let bit = 1 << (SCREEN_WIDTH - 1 - x);
+                match (top & bit != 0, bottom & bit != 0) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                }
+            })
+            .collect();
+
+        queue!(out, cursor::MoveTo(0, cell_row as u16), style::Print(line))?;
+    }
+
+    queue!(out, style::ResetColor)?;
+    out.flush()
+}
+
+// Puts the terminal back the way it was, even if the frontend panics
+struct TerminalGuard {
+    enhanced: bool,
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        if self.enhanced {
+            let _ = queue!(out, event::PopKeyboardEnhancementFlags);
+        }
+        let _ = queue!(out, style::ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = out.flush();
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+// Runs the machine in the terminal at 60 frames per second until Escape or
+// Ctrl+C, passing other keys on through `keys`. The sound timer rings the
+// terminal bell. A crash in the program comes back as an error.
+pub fn run(machine: &mut Machine, keys: TerminalKeys) -> io::Result<()> {
+    let mut out = io::stdout();
+
+    terminal::enable_raw_mode()?;
+    let enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+    let _guard = TerminalGuard { enhanced };
+
+    queue!(out, terminal::EnterAlternateScreen, cursor::Hide, terminal::Clear(terminal::ClearType::All))?;
+    if enhanced {
+        queue!(out, event::PushKeyboardEnhancementFlags(event::KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+    }
+    out.flush()?;
+
+    let mut sounding = false;
+
+    loop {
+        let start = time::Instant::now();
+
+        // Keys nothing read last frame, because another input is active,
+        // are dropped rather than piling up
+        keys.borrow_mut().clear();
+        while event::poll(time::Duration::ZERO)? {
+            if let Event::Key(key) = event::read()? {
+                if is_quit(&key) {
+                    return Ok(());
+                }
+                keys.borrow_mut().push(key);
+            }
+        }
+
+        machine.step_frame();
+        if let Some(crash) = machine.crash() {
+            return Err(io::Error::other(crash.to_string()));
+        }
+
+        if machine.sound_active() && !sounding {
+            queue!(out, style::Print('\x07'))?;
+        }
+        sounding = machine.sound_active();
+
+        if machine.take_display_dirty() {
+            draw(&mut out, machine)?;
+        } else {
+            out.flush()?;
+        }
+
+        if let Some(remaining) = FRAME_TIME.checked_sub(start.elapsed()) {
+            thread::sleep(remaining);
+        }
+    }
+}
@@ -0,0 +1,55 @@
+use std::{
+    fs,
+    time,
+};
+
+// How often the file's modification time is checked
+const POLL_INTERVAL: time::Duration = time::Duration::from_millis(250);
+
+// Notices when a file is rewritten by polling its modification time. A change
+// is only reported once the time has stayed the same for a whole poll, so a
+// file still being written by an assembler isn't picked up half-finished.
+pub struct FileWatcher {
+    path: String,
+    modified: Option<time::SystemTime>,
+    pending: bool,
+    last_poll: time::Instant,
+}
+
+fn modified(path: &str) -> Option<time::SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+impl FileWatcher {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            modified: modified(path),
+            pending: false,
+            last_poll: time::Instant::now(),
+        }
+    }
+
+    // Cheap enough to call every frame
+    pub fn changed(&mut self) -> bool {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return false;
+        }
+        self.last_poll = time::Instant::now();
+
+        let modified = modified(&self.path);
+        if modified != self.modified {
+            self.modified = modified;
+            self.pending = true;
+            return false;
+        }
+
+        // Missing files (mid-save by some editors) count as still changing
+        if self.pending && modified.is_some() {
+            self.pending = false;
+            return true;
+        }
+
+        false
+    }
+}
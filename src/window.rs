@@ -0,0 +1,192 @@
+use macroquad::prelude::*;
+use crate::capture::Frame;
+use crate::chip8::{Machine, Status};
+use crate::editor::MemoryEditor;
+use crate::heatmap::HeatmapPanel;
+use crate::keymap;
+use crate::persistence::{self, PersistenceFilter, PersistenceMode};
+use crate::render::{ScaleMode, ScreenRenderer};
+
+// Display settings for the window, all of which can also be changed with
+// hotkeys while it runs
+#[derive(Clone, Copy)]
+pub struct WindowOptions {
+    // Flicker reduction for what's drawn; screenshots and recordings always
+    // show the raw framebuffer
+    pub persistence: Option<PersistenceMode>,
+    pub scale_mode: ScaleMode,
+    pub fullscreen: bool,
+}
+
+// Off, then fading, then blending the last two frames
+fn next_persistence(mode: Option<PersistenceMode>) -> Option<PersistenceMode> {
+    match mode {
+        None => Some(PersistenceMode::Fade(persistence::DEFAULT_FADE_FRAMES)),
+        Some(PersistenceMode::Fade(_)) => Some(PersistenceMode::Blend),
+        Some(PersistenceMode::Blend) => None,
+    }
+}
+
+// Runs the game in the window until the player presses Escape or the program
+// crashes; Machine::crash says why
+pub async fn run(machine: &mut Machine, options: WindowOptions) {
+    let scale_ratio: f32 = 16.0;
+    request_new_screen_size(64.0 * scale_ratio, 32.0 * scale_ratio);
+
+    let mut scale_mode = options.scale_mode;
+    let mut fullscreen = options.fullscreen;
+    let mut renderer = ScreenRenderer::new(scale_mode);
+    set_fullscreen(fullscreen);
+
+    let mut persistence: Option<PersistenceFilter> = options.persistence.map(PersistenceFilter::new);
+    let mut heatmap: Option<HeatmapPanel> = None;
+    // Kept while closed so a memory search survives playing between looks
+    let mut editor = MemoryEditor::new(machine.pc());
+    let mut editing = false;
+    // Forces an upload when what's shown changes without the framebuffer
+    // changing, e.g. switching persistence
+    let mut redraw = true;
+
+    loop {
+        // Letterbox borders
+        clear_background(BLACK);
+
+        // Back to the ROM browser. Checked before F1 so the Escape that
+        // cancels a remap doesn't also leave the game, and not while the
+        // memory editor has the keyboard.
+        if is_key_pressed(KeyCode::Escape) && !editing {
+            return;
+        }
+
+        // The other hotkeys wait while the memory editor has the keyboard
+        if !editing && is_key_pressed(KeyCode::F1) {
+            if let Some(keymap) = machine.keymap_mut() {
+                if keymap::remap_screen(keymap).await {
+                    if let Err(why) = keymap.save() {
+                        panic!("{}", why);
+                    }
+                }
+            }
+        }
+
+        if !editing && is_key_pressed(KeyCode::F12) {
+            let path = format!("screenshot-{}.png", machine.frame());
+            if let Err(why) = machine.screenshot(&path, machine.screenshot_scale()) {
+                panic!("{}", why);
+            }
+        }
+
+        if !editing && is_key_pressed(KeyCode::F10) {
+            if machine.recording_gif() {
+                machine.stop_gif_recording();
+            } else {
+                let path = format!("recording-{}.gif", machine.frame());
+                machine.start_gif_recording(&path, 1, None);
+            }
+        }
+
+        if !editing && is_key_pressed(KeyCode::F2) {
+            machine.cycle_palette();
+        }
+
+        if !editing && is_key_pressed(KeyCode::F3) {
+            persistence = next_persistence(persistence.as_ref().map(|filter| filter.mode())).map(PersistenceFilter::new);
+            redraw = true;
+        }
+
+        if !editing && is_key_pressed(KeyCode::F4) {
+            scale_mode = scale_mode.next();
+            renderer.set_scale_mode(scale_mode);
+        }
+
+        if !editing && is_key_pressed(KeyCode::F11) {
+            fullscreen = !fullscreen;
+            set_fullscreen(fullscreen);
+        }
+
+        if !editing && is_key_pressed(KeyCode::F9) {
+            if machine.recording_av() {
+                machine.stop_av_recording();
+            } else {
+                let base = format!("recording-{}", machine.frame());
+                machine.start_av_recording(&base, None);
+            }
+        }
+
+        // Backspace restarts the ROM, Shift+Backspace also resets the frame
+        // count and stops recordings. Keymaps can't bind Backspace, so no game
+        // key resets too.
+        if !editing && is_key_pressed(KeyCode::Backspace) {
+            if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+                machine.hard_reset();
+            } else {
+                machine.reset();
+            }
+        }
+
+        // The state hot reloads return to
+        if !editing && is_key_pressed(KeyCode::F7) {
+            machine.keep_reload_state();
+        }
+
+        if !editing && is_key_pressed(KeyCode::F8) {
+            if let Err(why) = machine.write_profile() {
+                panic!("{}", why);
+            }
+        }
+
+        if !editing && is_key_pressed(KeyCode::F6) {
+            heatmap = match heatmap {
+                Some(_) => None,
+                None => Some(HeatmapPanel::default()),
+            };
+            machine.set_heatmap(heatmap.is_some());
+        }
+
+        if is_key_pressed(KeyCode::F5) {
+            editing = !editing;
+            if editing {
+                editor.open(machine.pc());
+            }
+        }
+
+        // The game is paused while the memory editor is open, and the
+        // editor gets the keyboard
+        if editing {
+            editor.update(machine);
+        } else {
+            // Once halted step_frame no longer executes anything, so the
+            // loop just keeps drawing and handling hotkeys
+            let was_running = machine.status() == Status::Running;
+            machine.step_frame();
+            if was_running && machine.status() == Status::Halted {
+                println!("Halted at {}", machine.symbols().name(machine.pc()));
+            }
+            if let Some(crash) = machine.crash() {
+                eprintln!("{}", crash);
+                return;
+            }
+        }
+
+        // Fading pixels change every frame even when the framebuffer doesn't
+        let dirty = machine.take_display_dirty() || std::mem::take(&mut redraw);
+        if let Some(ref mut filter) = persistence {
+            filter.update(machine);
+            renderer.upload(&filter.frame(machine.palette()));
+        } else if dirty {
+            renderer.upload(&Frame::render(machine, 1, machine.palette()));
+        }
+        renderer.draw();
+
+        if let (Some(panel), Some(memory)) = (heatmap.as_mut(), machine.heatmap_mut()) {
+            let size: f32 = 256.0;
+            panel.draw(memory, Rect::new(screen_width() - size - 12.0, 12.0, size, size));
+        }
+
+        if editing {
+            editor.draw(machine);
+        }
+
+        next_frame().await;
+    }
+}